@@ -0,0 +1,148 @@
+//! Per-frame cost of drawing large scenes: the graphics tree, walked with
+//! `Nat` arithmetic (as `ic-mt` drew before display lists), versus its
+//! display list (compiled, and then drawn).
+//!
+//! Run with `cargo bench --bench display_list`.
+
+use candid::Nat;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::{Canvas, RenderTarget};
+use sdl2::surface::Surface;
+
+use icmt::display::DisplayList;
+use icmt::draw::draw_list;
+use icmt::types::{byte_ceil, graphics, nat_ceil};
+
+const FRAME: u32 = 1024;
+
+/// A grid of `n` by `n` cells, each a node with an outline and a filled
+/// square (like a text grid).
+fn scene(n: u32) -> graphics::Result {
+    let cell = FRAME / n;
+    let nat = |v: u32| Nat::from(v);
+    let color = |i: u32| (nat(i % 256), nat((i * 7) % 256), nat((i * 13) % 256));
+    let mut cells = vec![];
+    for row in 0..n {
+        for col in 0..n {
+            let i = row * n + col;
+            cells.push(graphics::Elm::Node(Box::new(graphics::Node {
+                rect: graphics::Rect::new(nat(col * cell), nat(row * cell), nat(cell), nat(cell)),
+                fill: graphics::Fill::Open(color(i), nat(1)),
+                elms: vec![graphics::Elm::Rect(
+                    graphics::Rect::new(nat(2), nat(2), nat(cell - 4), nat(cell - 4)),
+                    graphics::Fill::Closed(color(i + 1)),
+                )],
+            })));
+        }
+    }
+    graphics::Result::Ok(graphics::Out::Draw(graphics::Elm::Node(Box::new(
+        graphics::Node {
+            rect: graphics::Rect::new(nat(0), nat(0), nat(FRAME), nat(FRAME)),
+            fill: graphics::Fill::None,
+            elms: cells,
+        },
+    ))))
+}
+
+/// The tree walk, with a `Nat` addition per node and rectangle.
+mod tree_walk {
+    use super::*;
+
+    fn draw_rect<T: RenderTarget>(
+        canvas: &mut Canvas<T>,
+        pos: &graphics::Pos,
+        r: &graphics::Rect,
+        f: &graphics::Fill,
+    ) {
+        let rect = || {
+            sdl2::rect::Rect::new(
+                nat_ceil(&Nat(&pos.x.0 + &r.pos.x.0)) as i32,
+                nat_ceil(&Nat(&pos.y.0 + &r.pos.y.0)) as i32,
+                nat_ceil(&r.dim.width),
+                nat_ceil(&r.dim.height),
+            )
+        };
+        let color = |(r, g, b): &graphics::Color| {
+            sdl2::pixels::Color::RGB(byte_ceil(r), byte_ceil(g), byte_ceil(b))
+        };
+        match f {
+            graphics::Fill::None => (),
+            graphics::Fill::Closed(c) => {
+                canvas.set_draw_color(color(c));
+                canvas.fill_rect(rect()).unwrap();
+            }
+            graphics::Fill::Open(c, _) => {
+                canvas.set_draw_color(color(c));
+                canvas.draw_rect(rect()).unwrap();
+            }
+        }
+    }
+
+    fn draw_elm<T: RenderTarget>(
+        canvas: &mut Canvas<T>,
+        pos: &graphics::Pos,
+        elm: &graphics::Elm,
+    ) {
+        match elm {
+            graphics::Elm::Node(node) => {
+                let pos = graphics::Pos {
+                    x: Nat(&pos.x.0 + &node.rect.pos.x.0),
+                    y: Nat(&pos.y.0 + &node.rect.pos.y.0),
+                };
+                let bound = graphics::Rect::new(
+                    Nat::from(0),
+                    Nat::from(0),
+                    node.rect.dim.width.clone(),
+                    node.rect.dim.height.clone(),
+                );
+                draw_rect(canvas, &pos, &bound, &node.fill);
+                for elm in node.elms.iter() {
+                    draw_elm(canvas, &pos, elm)
+                }
+            }
+            graphics::Elm::Rect(r, f) => draw_rect(canvas, pos, r, f),
+        }
+    }
+
+    pub fn draw_frame<T: RenderTarget>(canvas: &mut Canvas<T>, rr: &graphics::Result) {
+        if let graphics::Result::Ok(graphics::Out::Draw(elm)) = rr {
+            let pos = graphics::Pos {
+                x: Nat::from(0),
+                y: Nat::from(0),
+            };
+            canvas.set_draw_color(sdl2::pixels::Color::RGB(0, 0, 0));
+            canvas
+                .fill_rect(sdl2::rect::Rect::new(0, 0, FRAME, FRAME))
+                .unwrap();
+            draw_elm(canvas, &pos, elm)
+        }
+    }
+}
+
+fn bench_frames(c: &mut Criterion) {
+    let surface = Surface::new(FRAME, FRAME, PixelFormatEnum::RGBA8888).unwrap();
+    let mut canvas = surface.into_canvas().unwrap();
+    let dim = graphics::Dim {
+        width: Nat::from(FRAME),
+        height: Nat::from(FRAME),
+    };
+    let mut group = c.benchmark_group("frame");
+    for n in [32u32, 128, 256].iter() {
+        let rr = scene(*n);
+        let list = DisplayList::compile(&dim, &rr);
+        group.bench_with_input(BenchmarkId::new("tree walk", n), &rr, |b, rr| {
+            b.iter(|| tree_walk::draw_frame(&mut canvas, rr))
+        });
+        group.bench_with_input(BenchmarkId::new("compile and draw", n), &rr, |b, rr| {
+            b.iter(|| draw_list(&mut canvas, &DisplayList::compile(&dim, rr)).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("draw compiled", n), &list, |b, list| {
+            b.iter(|| draw_list(&mut canvas, list).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_frames);
+criterion_main!(benches);
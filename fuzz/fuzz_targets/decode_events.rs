@@ -0,0 +1,13 @@
+//! Decode an events file (as `ic-mt replay` does): hex text, or its bytes.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use icmt::types::event;
+
+fuzz_target!(|data: &[u8]| {
+    let bytes = match std::str::from_utf8(data).ok().and_then(|t| hex::decode(t).ok()) {
+        Some(bytes) => bytes,
+        None => data.to_vec(),
+    };
+    let _ = candid::Decode!(&bytes, Vec<event::EventInfo>);
+});
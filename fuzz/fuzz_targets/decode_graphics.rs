@@ -0,0 +1,28 @@
+//! Decode a view or update response (as `service_call` does, checking its
+//! size and nesting first), and check it against the (default) limits.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use icmt::{limits::Limits, types::graphics};
+
+thread_local! {
+    static LIMITS: Limits = Limits::default();
+}
+
+fuzz_target!(|data: &[u8]| {
+    LIMITS.with(|limits| {
+        if limits
+            .check_bytes(data.len())
+            .and_then(|()| limits.check_nesting(data))
+            .is_err()
+        {
+            return;
+        }
+        if let Ok(res) = candid::Decode!(data, graphics::Result) {
+            let _ = limits.check_graphics(&[res]);
+        }
+        if let Ok(res) = candid::Decode!(data, Vec<graphics::Result>) {
+            let _ = limits.check_graphics(&res);
+        }
+    })
+});
@@ -0,0 +1,33 @@
+//! Decode a view response and, when it is within the (default) limits,
+//! draw it offscreen (as a capture does).
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use icmt::{display::DisplayList, draw::draw_list, limits::Limits, types::graphics};
+
+thread_local! {
+    static LIMITS: Limits = Limits::default();
+}
+
+fuzz_target!(|data: &[u8]| {
+    LIMITS.with(|limits| {
+        if limits.check_nesting(data).is_err() {
+            return;
+        }
+        let res = match candid::Decode!(data, graphics::Result) {
+            Ok(res) => res,
+            Err(_) => return,
+        };
+        if limits.check_graphics(&[res.clone()]).is_err() {
+            return;
+        }
+        let dim = graphics::Dim {
+            width: candid::Nat::from(320),
+            height: candid::Nat::from(240),
+        };
+        let surface =
+            sdl2::surface::Surface::new(320, 240, sdl2::pixels::PixelFormatEnum::RGBA8888).unwrap();
+        let mut canvas = surface.into_canvas().unwrap();
+        draw_list(&mut canvas, &DisplayList::compile(&dim, &res)).unwrap();
+    })
+});
@@ -0,0 +1,24 @@
+#!/bin/sh
+# Seed the fuzz corpora with the captures in test/input: events files
+# (hex text, and its bytes) and graphics files (bytes), and with the
+# graphics files of the golden tests.  (Each corpus also has checked-in
+# seeds.)  Run from the repository's root.
+
+set -e
+
+for f in test/input/*-events.did; do
+    [ -f "$f" ] || continue
+    name=`basename "$f" .did`
+    mkdir -p fuzz/corpus/decode_events
+    cp "$f" "fuzz/corpus/decode_events/$name.hex"
+    xxd -r -p "$f" > "fuzz/corpus/decode_events/$name.bin"
+done
+
+for f in test/input/*-graphics.did tests/golden/captured/*-graphics.did; do
+    [ -f "$f" ] || continue
+    name=`basename "$f" .did`
+    for target in decode_graphics render_graphics; do
+        mkdir -p "fuzz/corpus/$target"
+        xxd -r -p "$f" > "fuzz/corpus/$target/$name.bin"
+    done
+done
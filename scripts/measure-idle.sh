@@ -0,0 +1,56 @@
+#!/bin/sh
+# Measure the idle CPU use, and wakeups, of ic-mt before and after a
+# change: build both revisions, run each (without a window, input or idle
+# refresh) against the same recorded trace, and report its CPU time and
+# its main loop's wakeups (voluntary context switches), per second.
+# It prints them as rows of the README's measured table, after the
+# machine and the trace that they were measured on.
+#
+# usage: scripts/measure-idle.sh TRACE CANISTER [BEFORE [AFTER [SECONDS]]]
+#
+# TRACE is a file recorded with --record-trace; BEFORE defaults to the
+# last revision that polled for input (every 13 ms), and AFTER to HEAD.
+set -eu
+
+trace=$(realpath "$1")
+canister=$2
+before=${3:-5ca09e1}
+after=${4:-HEAD}
+secs=${5:-30}
+
+root=$(git rev-parse --show-toplevel)
+work=$(mktemp -d)
+trap 'rm -rf "$work"' EXIT
+ticks=$(getconf CLK_TCK)
+
+sample() {
+    # CPU time (in clock ticks, all threads) and the main thread's wakeups.
+    cpu=$(awk '{ print $14 + $15 }' "/proc/$1/stat")
+    wakeups=$(awk '/^voluntary_ctxt_switches/ { print $2 }' "/proc/$1/status")
+    echo "$cpu $wakeups"
+}
+
+cpu_model=$(awk -F': ' '/^model name/ { print $2; exit }' /proc/cpuinfo)
+echo "Machine: $cpu_model, $(nproc) CPUs, $(uname -sr)"
+echo "Trace: $(basename "$trace") ($(wc -l < "$trace") calls), ${secs} s idle"
+echo
+echo "| Build | Idle CPU | Idle wakeups |"
+echo "|-------|----------|--------------|"
+
+for rev in "$before" "$after"; do
+    dir="$work/$rev"
+    mkdir -p "$dir"
+    git -C "$root" archive "$rev" | tar -x -C "$dir"
+    cargo build --release --quiet --manifest-path "$dir/Cargo.toml"
+    "$dir/target/release/ic-mt" --no-window --no-capture --refresh-interval 0 \
+        --offline-trace "$trace" connect http://localhost "$canister" >/dev/null 2>&1 &
+    pid=$!
+    sleep 5 # (past startup)
+    set -- $(sample $pid)
+    sleep "$secs"
+    set -- "$@" $(sample $pid)
+    kill $pid
+    wait $pid 2>/dev/null || true
+    echo "$1 $2 $3 $4" | awk -v rev="$rev" -v t="$ticks" -v s="$secs" \
+        '{ printf "| %s | %.2f%% | %.1f/s |\n", rev, 100 * ($3 - $1) / t / s, ($4 - $2) / s }'
+done
@@ -0,0 +1,429 @@
+//! Bridge: a session, served to web browsers (with `--serve`).
+//!
+//! An embedded HTTP server gives each browser a small page, which opens a
+//! WebSocket.  Over it, the bridge streams each frame (as its display list,
+//! in JSON), and takes the browser's keyboard and mouse input, which joins
+//! the session's local input (as if from the local user).  So one session
+//! drives both the window and every browser viewer.
+//!
+//! Since input from a browser reaches the canister as the local user's
+//! (signed with their identity), the bridge serves only browsers that
+//! know the session's random token (in the page's URL), and accepts a
+//! WebSocket only from a page on its own host (so that other web sites,
+//! open in the same browser, cannot send input).
+
+use futures::{SinkExt, StreamExt};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::pin::Pin;
+use std::sync::{mpsc, Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::display::DisplayList;
+use crate::error::{io_err, IcmtError, IcmtResult};
+use crate::frontend::{CaptureSink, Input, InputSource, Waker};
+use crate::handshake::Capabilities;
+use crate::types::{event, graphics, nat_ceil, Nat};
+
+/// Frames kept for each browser that falls behind (it skips the older ones).
+const FRAME_BACKLOG: usize = 4;
+
+/// Limit on the size of a request's head (its request line and headers).
+const MAX_HEAD_BYTES: usize = 8192;
+
+const PAGE: &str = include_str!("bridge.html");
+
+/// A frame, for browsers: its size, and its rectangles as `[x, y, width, height, r, g, b]`.
+#[derive(Serialize)]
+struct FrameMsg {
+    width: u32,
+    height: u32,
+    rects: Vec<[i64; 7]>,
+}
+
+/// Input from a browser.
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum BrowserInput {
+    #[serde(rename = "keyDown")]
+    KeyDown {
+        key: String,
+        #[serde(default)]
+        alt: bool,
+        #[serde(default)]
+        ctrl: bool,
+        #[serde(default)]
+        meta: bool,
+        #[serde(default)]
+        shift: bool,
+    },
+    #[serde(rename = "mouseDown")]
+    MouseDown { x: u64, y: u64 },
+}
+
+struct Shared {
+    token: String,
+    frames: broadcast::Sender<Arc<String>>,
+    last_frame: Mutex<Option<Arc<String>>>,
+    /// The last frame's size (none before the first), for clipping mouse input.
+    frame_size: Mutex<(u32, u32)>,
+    input_out: Mutex<mpsc::Sender<Input>>,
+    wake: Waker,
+}
+
+/// Sends each frame to the browsers.
+pub struct BridgeSink {
+    shared: Arc<Shared>,
+    caps: Capabilities,
+}
+
+/// The session's local input, joined by the browsers' input.
+pub struct BridgeInput {
+    local: Box<dyn InputSource>,
+    remote_in: mpsc::Receiver<Input>,
+}
+
+/// A bridge, bound to its address, but not yet serving.
+pub struct Bridge {
+    listener: TcpListener,
+    token: String,
+    url: String,
+}
+
+/// Bind a bridge to `addr` (e.g., `127.0.0.1:8080`), with a fresh token.
+pub async fn bind(addr: &str) -> IcmtResult<Bridge> {
+    use ring::rand::SecureRandom;
+    let listener = TcpListener::bind(addr).await.map_err(io_err(addr))?;
+    let mut bytes = [0u8; 16];
+    ring::rand::SystemRandom::new().fill(&mut bytes)?;
+    let token = hex::encode(bytes);
+    let url = format!("http://{}/{}", addr, token);
+    info!("Serving the session at {}", url);
+    Ok(Bridge {
+        listener,
+        token,
+        url,
+    })
+}
+
+impl Bridge {
+    /// The session's page (with its token), for browsers.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Serve the session, with its local input.
+    pub fn serve(self, local: Box<dyn InputSource>) -> (BridgeSink, BridgeInput) {
+        let (input_out, remote_in) = mpsc::channel();
+        let shared = Arc::new(Shared {
+            token: self.token,
+            frames: broadcast::channel(FRAME_BACKLOG).0,
+            last_frame: Mutex::new(None),
+            frame_size: Mutex::new((0, 0)),
+            input_out: Mutex::new(input_out),
+            wake: local.waker(),
+        });
+        tokio::spawn(accept(self.listener, shared.clone()));
+        let sink = BridgeSink {
+            shared,
+            caps: Capabilities::all(),
+        };
+        (sink, BridgeInput { local, remote_in })
+    }
+}
+
+/// Accept browser connections, each with a task of its own.
+async fn accept(listener: TcpListener, server: Arc<Shared>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let shared = server.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle(stream, shared).await {
+                        info!("Browser {}: {}", peer, e);
+                    }
+                });
+            }
+            Err(e) => {
+                warn!("Cannot accept browser connection: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+/// A connection, with the bytes already read from it (its request's head) put back.
+struct Replayed {
+    head: Vec<u8>,
+    pos: usize,
+    stream: TcpStream,
+}
+
+impl AsyncRead for Replayed {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        if self.pos < self.head.len() {
+            let (pos, n) = (self.pos, buf.remaining().min(self.head.len() - self.pos));
+            buf.put_slice(&self.head[pos..pos + n]);
+            self.pos += n;
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Replayed {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+/// Read a request's head, through the blank line that ends it.
+async fn read_head(stream: &mut TcpStream) -> IcmtResult<Vec<u8>> {
+    let mut head = vec![];
+    let mut buf = [0; 2048];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        if head.len() > MAX_HEAD_BYTES {
+            return Err(IcmtError::String(format!(
+                "request head over {} bytes",
+                MAX_HEAD_BYTES
+            )));
+        }
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            return Err(IcmtError::String("connection closed mid-request".to_string()));
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+    Ok(head)
+}
+
+/// Serve the page (at the token's path), or (for its WebSocket) the session.
+async fn handle(mut stream: TcpStream, shared: Arc<Shared>) -> IcmtResult<()> {
+    let head = read_head(&mut stream).await?;
+    let text = String::from_utf8_lossy(&head).to_string();
+    if text.to_ascii_lowercase().contains("upgrade: websocket") {
+        let stream = Replayed {
+            head,
+            pos: 0,
+            stream,
+        };
+        return session(stream, shared).await;
+    }
+    let path = text.split_whitespace().nth(1).unwrap_or("").to_string();
+    let response = if path == format!("/{}", shared.token) {
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            PAGE.len(),
+            PAGE
+        )
+    } else {
+        info!("Not serving path {:?} (no token)", path);
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+    stream.write_all(response.as_bytes()).await?;
+    Ok(())
+}
+
+/// Accept a WebSocket only at the token's path, and only from a page on
+/// this host: a browser sends the page's origin, which other web sites
+/// cannot forge.
+fn check_upgrade(token: &str, request: &Request) -> Result<(), ErrorResponse> {
+    let header = |name: &str| request.headers().get(name).and_then(|v| v.to_str().ok());
+    let reason = if request.uri().path() != format!("/{}/ws", token) {
+        Some("unknown path")
+    } else {
+        match (header("origin"), header("host")) {
+            (Some(origin), Some(host)) if origin == format!("http://{}", host) => None,
+            _ => Some("origin is not this host"),
+        }
+    };
+    match reason {
+        None => Ok(()),
+        Some(reason) => {
+            info!(
+                "Rejecting WebSocket from origin {:?}: {}",
+                header("origin"),
+                reason
+            );
+            let mut response = ErrorResponse::new(Some(reason.to_string()));
+            *response.status_mut() = StatusCode::FORBIDDEN;
+            Err(response)
+        }
+    }
+}
+
+fn ws_err(e: tokio_tungstenite::tungstenite::Error) -> IcmtError {
+    IcmtError::String(format!("WebSocket: {}", e))
+}
+
+/// Stream frames to a browser, and take its input.
+async fn session(stream: Replayed, shared: Arc<Shared>) -> IcmtResult<()> {
+    let token = shared.token.clone();
+    let ws = tokio_tungstenite::accept_hdr_async(
+        stream,
+        move |request: &Request, response: Response| {
+            check_upgrade(&token, request).map(|()| response)
+        },
+    )
+    .await
+    .map_err(ws_err)?;
+    let (mut ws_out, mut ws_in) = ws.split();
+    let mut frames = shared.frames.subscribe();
+    let last_frame = shared.last_frame.lock().unwrap().clone();
+    if let Some(frame) = last_frame {
+        ws_out
+            .send(Message::Text((*frame).clone()))
+            .await
+            .map_err(ws_err)?;
+    }
+    loop {
+        tokio::select! {
+            frame = frames.recv() => match frame {
+                Ok(frame) => ws_out.send(Message::Text((*frame).clone())).await.map_err(ws_err)?,
+                Err(broadcast::error::RecvError::Lagged(_)) => (),
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            },
+            msg = ws_in.next() => match msg {
+                Some(Ok(Message::Text(text))) => shared.input(&text),
+                Some(Ok(Message::Close(_))) | None => return Ok(()),
+                Some(Ok(_)) => (),
+                Some(Err(e)) => return Err(ws_err(e)),
+            },
+        }
+    }
+}
+
+impl Shared {
+    /// Forward a browser's input to the session (and wake it).
+    fn input(&self, text: &str) {
+        let event = match serde_json::from_str::<BrowserInput>(text) {
+            Ok(BrowserInput::KeyDown {
+                key,
+                alt,
+                ctrl,
+                meta,
+                shift,
+            }) => event::Event::KeyDown(vec![event::KeyEventInfo {
+                key,
+                alt,
+                ctrl,
+                meta,
+                shift,
+            }]),
+            Ok(BrowserInput::MouseDown { x, y }) => {
+                let (width, height) = *self.frame_size.lock().unwrap();
+                if x >= width as u64 || y >= height as u64 {
+                    return;
+                }
+                event::Event::MouseDown(graphics::Pos {
+                    x: Nat::from(x),
+                    y: Nat::from(y),
+                })
+            }
+            Err(e) => {
+                info!("Ignoring browser input {:?}: {}", text, e);
+                return;
+            }
+        };
+        if self
+            .input_out
+            .lock()
+            .unwrap()
+            .send(Input::Event(event))
+            .is_ok()
+        {
+            (self.wake)();
+        }
+    }
+}
+
+impl CaptureSink for BridgeSink {
+    fn frame(&mut self, dim: &graphics::Dim, frame: &graphics::Result) -> IcmtResult<()> {
+        let list = DisplayList::compile_for(dim, frame, &self.caps);
+        let msg = FrameMsg {
+            width: nat_ceil(&dim.width),
+            height: nat_ceil(&dim.height),
+            rects: list
+                .rects
+                .iter()
+                .map(|r| {
+                    let (red, green, blue) = r.color;
+                    [
+                        r.x as i64,
+                        r.y as i64,
+                        r.width as i64,
+                        r.height as i64,
+                        red as i64,
+                        green as i64,
+                        blue as i64,
+                    ]
+                })
+                .collect(),
+        };
+        let msg = Arc::new(serde_json::to_string(&msg).map_err(|e| e.to_string())?);
+        *self.shared.frame_size.lock().unwrap() = (nat_ceil(&dim.width), nat_ceil(&dim.height));
+        *self.shared.last_frame.lock().unwrap() = Some(msg.clone());
+        // (no browsers, no receivers.)
+        let _ = self.shared.frames.send(msg);
+        Ok(())
+    }
+
+    fn resize(&mut self, _old_dim: &graphics::Dim, _dim: &graphics::Dim) -> IcmtResult<()> {
+        Ok(()) // (each frame has its size.)
+    }
+
+    fn finish(
+        &mut self,
+        _dim: &graphics::Dim,
+        _events: &Vec<event::EventInfo>,
+        _graphics: &Vec<graphics::Result>,
+    ) -> IcmtResult<()> {
+        Ok(())
+    }
+
+    fn negotiated(&mut self, caps: &Capabilities) {
+        self.caps = caps.clone();
+    }
+}
+
+impl InputSource for BridgeInput {
+    fn next_input(&mut self, timeout: Duration) -> IcmtResult<Option<Input>> {
+        if let Ok(input) = self.remote_in.try_recv() {
+            return Ok(Some(input));
+        }
+        match self.local.next_input(timeout)? {
+            Some(input) => Ok(Some(input)),
+            None => Ok(self.remote_in.try_recv().ok()),
+        }
+    }
+
+    fn waker(&self) -> Waker {
+        self.local.waker()
+    }
+}
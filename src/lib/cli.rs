@@ -1,10 +1,13 @@
 //! Command line interface.
 
 use clap::Shell;
+use std::time::Duration;
 use structopt::StructOpt;
 
-use ic_agent::Agent;
-use ic_types::Principal;
+use crate::error::{io_err, IcmtError, IcmtResult};
+use crate::limits::Limits;
+use crate::session::SessionOptions;
+use crate::types::UserProfile;
 
 /// Internet Computer Mini Terminal (ic-mt)
 #[derive(StructOpt, Debug, Clone)]
@@ -16,15 +19,33 @@ pub struct CliOpt {
     /// Frame rate (uniform) for producing captured GIF files with engiffen.
     #[structopt(long = "engiffen-framerate", default_value = "6")]
     pub engiffen_frame_rate: usize,
+    /// Initial window size, as WIDTHxHEIGHT.
+    #[structopt(long = "size", default_value = "500x400")]
+    pub window_size: Size,
+    /// Open the window in fullscreen mode.
+    #[structopt(long = "fullscreen")]
+    pub fullscreen: bool,
+    /// Fixed logical resolution (WIDTHxHEIGHT) for the canister; the window scales it by an integer factor.
+    #[structopt(long = "logical-size")]
+    pub logical_size: Option<Size>,
+    /// Send mouse presses (clicks) to the canister, as mouseDown events.
+    #[structopt(long = "mouse")]
+    pub mouse: bool,
     /// Suppress window for graphics output.
     #[structopt(short = "W", long = "no-window")]
     pub no_window: bool,
     /// Suppress capturing video and graphics output.
     #[structopt(short = "C", long = "no-capture")]
     pub no_capture: bool,
+    /// Capture video and graphics output, even if the profile says not to.
+    #[structopt(long = "capture", raw(conflicts_with = "\"no_capture\""))]
+    pub capture: bool,
     /// Dump all graphics for updates; for generating replay tests.
     #[structopt(short = "G", long = "all-graphics")]
     pub all_graphics: bool,
+    /// Dump no graphics for updates, even if the profile says to.
+    #[structopt(long = "no-all-graphics", raw(conflicts_with = "\"all_graphics\""))]
+    pub no_all_graphics: bool,
     /// Trace-level logging (most verbose)
     #[structopt(short = "t", long = "trace-log")]
     pub log_trace: bool,
@@ -34,6 +55,67 @@ pub struct CliOpt {
     /// Coarse logging information (not verbose)
     #[structopt(short = "L", long = "log")]
     pub log_info: bool,
+    /// Network (of the dfx project) for resolving canister names and the default replica URL.
+    #[structopt(long = "network", default_value = "local")]
+    pub network: String,
+    /// Configuration file with named profiles (default: icmt/config.toml in the user's config directory).
+    #[structopt(long = "config")]
+    pub config_path: Option<String>,
+    /// Identity file (PEM) for signing calls (default: a fresh, random identity).
+    #[structopt(long = "identity")]
+    pub identity_pem: Option<String>,
+    /// Record every service call (arguments and raw responses) to this trace file.
+    #[structopt(long = "record-trace")]
+    pub record_trace: Option<String>,
+    /// Serve responses from this trace file, instead of the network.
+    #[structopt(long = "offline-trace", raw(conflicts_with = "\"record_trace\""))]
+    pub offline_trace: Option<String>,
+    /// Export a record of every service call to this file (JSON if named .json or .jsonl, else CSV).
+    #[structopt(long = "metrics")]
+    pub metrics_path: Option<String>,
+    /// Skip checking the canister's interface against the terminal's, at startup.
+    #[structopt(long = "no-interface-check")]
+    pub no_interface_check: bool,
+    /// Give up on a failing service call after this many retries (0 = never give up).
+    /// A retried update may reach the canister twice: it should ignore events whose sessionId and nonce it has seen.
+    #[structopt(long = "max-retries", default_value = "20")]
+    pub max_retries: usize,
+    /// Re-query the view after this many idle milliseconds, to show other users' changes (0 = never).
+    #[structopt(long = "refresh-interval", default_value = "250")]
+    pub refresh_interval_ms: u64,
+    /// Longest idle refresh interval, in milliseconds; refreshes back off to it while the view is unchanged.
+    #[structopt(long = "refresh-max", default_value = "4000")]
+    pub refresh_max_ms: u64,
+    /// Send a tick event (with the elapsed milliseconds) at this interval, in milliseconds, for animation.
+    #[structopt(long = "tick")]
+    pub tick_interval_ms: Option<u64>,
+    /// Name of the canister's view (query) method.
+    #[structopt(long = "view-method", default_value = "view")]
+    pub view_method: String,
+    /// Name of the canister's update method.
+    #[structopt(long = "update-method", default_value = "update")]
+    pub update_method: String,
+    /// Extra leading arguments for view and update calls, as Candid text; e.g., '("lobby", 3)'.
+    #[structopt(long = "extra-arg")]
+    pub extra_arg: Option<String>,
+    /// Reject responses larger than this many bytes (default: the IC's own limit on a reply, 2 MiB).
+    #[structopt(long = "max-response-bytes", default_value = "2097152")]
+    pub max_response_bytes: usize,
+    /// Reject graphics with elements nested deeper than this.
+    #[structopt(long = "max-depth", default_value = "64")]
+    pub max_depth: usize,
+    /// Reject responses with more graphics elements than this.
+    #[structopt(long = "max-elements", default_value = "200000")]
+    pub max_elements: usize,
+    /// Reject graphics with an element wider or taller than this many pixels.
+    #[structopt(long = "max-dim", default_value = "16384")]
+    pub max_dim: u32,
+    /// Stop buffering graphics for capture (see --all-graphics) after this many elements, in all.
+    #[structopt(long = "max-captured-elements", default_value = "1000000")]
+    pub max_captured_elements: usize,
+    /// Serve the session to web browsers at this address (e.g., 127.0.0.1:8080); they see it, and send it input.
+    #[structopt(long = "serve")]
+    pub serve_addr: Option<String>,
     #[structopt(subcommand)]
     pub command: CliCommand,
 }
@@ -47,38 +129,333 @@ pub enum CliCommand {
     Completions { shell: Shell },
     #[structopt(name = "connect", about = "Connect to an IC canister.")]
     Connect {
-        replica_url: String,
-        canister_id: String,
-        /// Initialization arguments, as a Candid textual value (default is empty tuple).
-        #[structopt(short = "i", long = "user")]
-        user_info_text: String,
+        /// Replica URL, or host:port (default: from --profile, or from the dfx project's dfx.json).
+        replica_url: Option<String>,
+        /// Canister id, or name in the dfx project (optional with --profile).
+        canister_id: Option<String>,
+        /// Named profile from the configuration file; flags override its values.
+        #[structopt(long = "profile")]
+        profile: Option<String>,
+        /// Draw in this text terminal (with colored half blocks), instead of a window.
+        #[structopt(long = "tty")]
+        tty: bool,
+        #[structopt(flatten)]
+        user: UserOpt,
+    },
+    #[structopt(
+        name = "watch",
+        about = "Watch an IC canister, without sending it any events (spectator mode)."
+    )]
+    Watch {
+        /// Replica URL, or host:port (default: from the dfx project's dfx.json).
+        replica_url: Option<String>,
+        /// Canister id, or name in the dfx project.
+        canister_id: Option<String>,
+        /// Interval between view queries, in milliseconds (over 0).
+        #[structopt(long = "poll-interval", default_value = "500")]
+        poll_interval_ms: u64,
     },
     #[structopt(
         name = "replay",
         about = "Replay captured events as if they were live."
     )]
     Replay {
-        replica_url: String,
-        canister_id: String,
-        events_file_path: String,
+        /// Replica URL, or host:port (default: from the dfx project's dfx.json).
+        replica_url: Option<String>,
+        /// Canister id, or name in the dfx project.
+        canister_id: Option<String>,
+        /// Captured events file (hex).
+        events_file_path: Option<String>,
         /// Frame size, in number of events, for the replay's update requests.
         #[structopt(short = "s", long = "frame_size", default_value = "6")]
         frame_size: usize,
+        /// Event nonces: preserve the captured ones, or rewrite them under a new session.
+        #[structopt(long = "nonces", default_value = "preserve")]
+        nonce_mode: crate::types::NonceMode,
+    },
+    #[structopt(
+        name = "script",
+        about = "Run an input script (type \"hello\", key Enter, wait 500ms, ...) as a local user, without a window."
+    )]
+    Script {
+        /// Replica URL, or host:port (default: from the dfx project's dfx.json).
+        replica_url: Option<String>,
+        /// Canister id, or name in the dfx project.
+        canister_id: Option<String>,
+        /// Input script (see the README for its commands).
+        script_path: Option<String>,
+        #[structopt(flatten)]
+        user: UserOpt,
     },
 }
 
-/// Connection context: IC agent object, for server calls, and configuration info.
-pub struct ConnectCtx {
-    pub cfg: ConnectCfg,
-    pub agent: Agent,
-    pub canister_id: Principal,
+impl CliOpt {
+    /// Extra leading arguments for view and update calls (see `--extra-arg`).
+    ///
+    /// The parentheses are optional for a single argument.
+    pub fn extra_args(&self) -> IcmtResult<candid::IDLArgs> {
+        let text = match &self.extra_arg {
+            None => return Ok(candid::IDLArgs { args: vec![] }),
+            Some(text) if text.trim_start().starts_with('(') => text.clone(),
+            Some(text) => format!("({})", text),
+        };
+        text.parse::<candid::IDLArgs>()
+            .map_err(|e| IcmtError::Usage(format!("invalid --extra-arg {:?}: {}", text, e)))
+    }
+
+    /// Limits on each response (see `--max-response-bytes` and the other `--max-*` options).
+    pub fn limits(&self) -> Limits {
+        Limits {
+            max_response_bytes: self.max_response_bytes,
+            max_depth: self.max_depth,
+            max_elements: self.max_elements,
+            max_dim: self.max_dim,
+            max_captured_elements: self.max_captured_elements,
+        }
+    }
+
+    /// Session options, as given (and as the command selects `watch` and `replay` behavior).
+    pub fn session_options(&self) -> IcmtResult<SessionOptions> {
+        let ms = Duration::from_millis;
+        let refresh = match &self.command {
+            CliCommand::Watch {
+                poll_interval_ms: 0,
+                ..
+            } => {
+                return Err(IcmtError::Usage(
+                    "--poll-interval must be over 0 milliseconds".to_string(),
+                ));
+            }
+            CliCommand::Watch {
+                poll_interval_ms, ..
+            } => Some((ms(*poll_interval_ms), ms(*poll_interval_ms))),
+            _ if self.refresh_interval_ms > 0 => {
+                Some((ms(self.refresh_interval_ms), ms(self.refresh_max_ms)))
+            }
+            _ => None,
+        };
+        let frame_size = match &self.command {
+            CliCommand::Replay { frame_size, .. } => *frame_size,
+            _ => SessionOptions::default().frame_size,
+        };
+        Ok(SessionOptions {
+            view_method: self.view_method.clone(),
+            update_method: self.update_method.clone(),
+            extra_args: self.extra_args()?,
+            check_interface: !self.no_interface_check,
+            max_retries: self.max_retries,
+            refresh,
+            tick_interval: self.tick_interval_ms.map(ms),
+            frame_size,
+            all_graphics: self.all_graphics,
+            mouse: self.mouse,
+            limits: self.limits(),
+            record_trace: self.record_trace.clone(),
+            metrics_path: self.metrics_path.clone(),
+            window_dim: self.window_size.dim(),
+        })
+    }
+
+    /// Given one argument, `connect` (or `watch`) takes it as the
+    /// canister (id or name) unless it is a URL (or `host:port`); the
+    /// replica URL then comes from the profile, or the dfx project.
+    /// Likewise, given two, `replay` (or `script`) takes them as the
+    /// canister and its file.
+    ///
+    /// A replica given as `host:port` (e.g., `localhost:8000`) is taken
+    /// as `http://host:port`, by every command.
+    pub fn normalize_connect_args(&mut self) {
+        match &mut self.command {
+            CliCommand::Connect {
+                replica_url,
+                canister_id,
+                ..
+            }
+            | CliCommand::Watch {
+                replica_url,
+                canister_id,
+                ..
+            } => {
+                if let Some(arg) = replica_url.take() {
+                    match replica_url_of(&arg) {
+                        Some(url) => *replica_url = Some(url),
+                        None if canister_id.is_none() => *canister_id = Some(arg),
+                        None => *replica_url = Some(arg),
+                    }
+                }
+            }
+            CliCommand::Replay {
+                replica_url,
+                canister_id,
+                events_file_path: path,
+                ..
+            }
+            | CliCommand::Script {
+                replica_url,
+                canister_id,
+                script_path: path,
+                ..
+            } => {
+                if let Some(arg) = replica_url.take() {
+                    match replica_url_of(&arg) {
+                        Some(url) => *replica_url = Some(url),
+                        None if path.is_none() => {
+                            *path = canister_id.take();
+                            *canister_id = Some(arg);
+                        }
+                        None => *replica_url = Some(arg),
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// The replica URL that an argument gives, if any: a URL, or `host:port`
+/// (which canister ids and names never look like).
+fn replica_url_of(arg: &str) -> Option<String> {
+    if arg.contains("://") {
+        Some(arg.to_string())
+    } else if arg.contains(':') {
+        Some(format!("http://{}", arg))
+    } else {
+        None
+    }
+}
+
+/// User profile options: named flags, a profile file, or (both) a RON profile text.
+///
+/// Named flags override the file (or text), field by field.
+#[derive(StructOpt, Debug, Clone)]
+pub struct UserOpt {
+    /// User profile, as RON text: ("Alice", (100, 200, 200)) or (name: "Alice", fg: (100, 200, 200), bg: (0, 0, 0)).
+    #[structopt(short = "i", long = "user")]
+    pub user_info_text: Option<String>,
+    /// User profile file (RON record; see --user).
+    #[structopt(long = "user-file")]
+    pub user_file: Option<String>,
+    /// User name.
+    #[structopt(long = "user-name")]
+    pub user_name: Option<String>,
+    /// Foreground (text) color, as R,G,B or #RRGGBB.
+    #[structopt(long = "fg")]
+    pub fg: Option<Rgb>,
+    /// Background color, as R,G,B or #RRGGBB.
+    #[structopt(long = "bg")]
+    pub bg: Option<Rgb>,
+    /// Avatar (for example, an emoji or an image URL), passed along with each event.
+    #[structopt(long = "avatar")]
+    pub avatar: Option<String>,
+}
+
+impl UserOpt {
+    /// The user profile given by these options.
+    pub fn profile(&self) -> IcmtResult<UserProfile> {
+        let base = match (&self.user_file, &self.user_info_text) {
+            (Some(_), Some(_)) => {
+                return Err(IcmtError::Usage(
+                    "give at most one of --user and --user-file".to_string(),
+                ))
+            }
+            (Some(path), None) => {
+                let text = std::fs::read_to_string(path).map_err(io_err(path))?;
+                Some(
+                    UserProfile::from_ron(&text)
+                        .map_err(|e| IcmtError::Usage(format!("{}: {}", path, e)))?,
+                )
+            }
+            (None, Some(text)) => Some(
+                UserProfile::from_ron(text)
+                    .map_err(|e| IcmtError::Usage(format!("invalid --user {:?}: {}", text, e)))?,
+            ),
+            (None, None) => None,
+        };
+        let mut profile = match (base, &self.user_name) {
+            (_, Some(name)) if name.is_empty() => {
+                return Err(IcmtError::Usage("empty --user-name".to_string()))
+            }
+            (Some(base), Some(name)) => UserProfile {
+                name: name.clone(),
+                ..base
+            },
+            (Some(base), None) => base,
+            (None, Some(name)) => UserProfile::new(name.clone()),
+            (None, None) => {
+                return Err(IcmtError::Usage(
+                    "no user given; use --user-name (or --user, or --user-file)".to_string(),
+                ))
+            }
+        };
+        if let Some(Rgb(r, g, b)) = self.fg {
+            profile.fg = (r, g, b);
+        }
+        if let Some(Rgb(r, g, b)) = self.bg {
+            profile.bg = (r, g, b);
+        }
+        if let Some(avatar) = &self.avatar {
+            profile.avatar = Some(avatar.clone());
+        }
+        Ok(profile)
+    }
+}
+
+/// Color, given as R,G,B (decimal) or #RRGGBB (hex).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl std::str::FromStr for Rgb {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("expected a color like 100,200,200 or #64c8c8, not {:?}", s);
+        if s.starts_with('#') {
+            let bytes = hex::decode(&s[1..]).map_err(|_| err())?;
+            match bytes.as_slice() {
+                [r, g, b] => Ok(Rgb(*r, *g, *b)),
+                _ => Err(err()),
+            }
+        } else {
+            let parts: Vec<&str> = s.split(',').map(|p| p.trim()).collect();
+            match parts.as_slice() {
+                [r, g, b] => Ok(Rgb(
+                    r.parse().map_err(|_| err())?,
+                    g.parse().map_err(|_| err())?,
+                    b.parse().map_err(|_| err())?,
+                )),
+                _ => Err(err()),
+            }
+        }
+    }
+}
+
+/// Size (width and height, in pixels), given as WIDTHxHEIGHT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Size {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Size {
+    pub fn dim(&self) -> crate::types::graphics::Dim {
+        crate::types::graphics::Dim {
+            width: candid::Nat::from(self.width),
+            height: candid::Nat::from(self.height),
+        }
+    }
 }
 
-/// Connection configuration
-#[derive(Debug, Clone)]
-pub struct ConnectCfg {
-    pub cli_opt: CliOpt,
-    pub canister_id: String,
-    pub replica_url: String,
-    pub user_kind: crate::types::UserKind,
+impl std::str::FromStr for Size {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("expected a size like 640x480, not {:?}", s);
+        let mut parts = s.splitn(2, |c| c == 'x' || c == 'X');
+        let width = parts.next().ok_or_else(err)?.trim();
+        let height = parts.next().ok_or_else(err)?.trim();
+        let width: u32 = width.parse().map_err(|_| err())?;
+        let height: u32 = height.parse().map_err(|_| err())?;
+        if width == 0 || height == 0 {
+            return Err(err());
+        }
+        Ok(Size { width, height })
+    }
 }
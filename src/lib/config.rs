@@ -0,0 +1,201 @@
+//! Configuration file, with named connection profiles.
+//!
+//! The file (by default, `icmt/config.toml` in the user's configuration
+//! directory) holds a table of profiles; for example:
+//!
+//! ```toml
+//! [profiles.garden]
+//! replica_url = "http://127.0.0.1:8000"
+//! canister_id = "rwlgt-iiaaa-aaaaa-aaaaq-cai"
+//! identity = "/home/alice/.config/dfx/identity/default/identity.pem"
+//! log_level = "info"
+//! update_method = "gardenUpdate"
+//! extra_arg = '("north-room")'
+//!
+//! [profiles.garden.user]
+//! name = "Alice"
+//! fg = [100, 200, 200]
+//! bg = [0, 0, 0]
+//!
+//! [profiles.garden.capture]
+//! output_path = "./out"
+//! all_graphics = false
+//! ```
+//!
+//! Command-line flags override the values of the selected profile; for
+//! its booleans, `--capture` and `--no-all-graphics` override a true
+//! `no_capture` and `all_graphics`.
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::cli::{CliCommand, CliOpt, Rgb};
+use crate::error::{io_err, IcmtError, IcmtResult};
+use crate::types::UserProfile;
+
+/// Configuration file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// Named connection profile.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub replica_url: Option<String>,
+    pub canister_id: Option<String>,
+    /// Identity file (PEM), for signing calls.
+    pub identity: Option<String>,
+    pub user: Option<UserProfile>,
+    #[serde(default)]
+    pub capture: CaptureProfile,
+    /// One of `trace`, `debug`, `info` or `warn`.
+    pub log_level: Option<String>,
+    pub view_method: Option<String>,
+    pub update_method: Option<String>,
+    /// Extra leading arguments for view and update calls, as Candid text.
+    pub extra_arg: Option<String>,
+}
+
+/// Capture settings of a profile.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CaptureProfile {
+    pub output_path: Option<String>,
+    pub engiffen_framerate: Option<usize>,
+    pub no_capture: Option<bool>,
+    pub all_graphics: Option<bool>,
+}
+
+impl Config {
+    /// Default path of the configuration file.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("icmt").join("config.toml"))
+    }
+
+    pub fn load(path: &PathBuf) -> IcmtResult<Config> {
+        let text = std::fs::read_to_string(path).map_err(io_err(path))?;
+        toml::from_str(&text)
+            .map_err(|e| IcmtError::Usage(format!("{}: {}", path.display(), e)))
+    }
+
+    /// The profile selected on the command line, if any.
+    pub fn selected_profile(cli_opt: &CliOpt) -> IcmtResult<Option<Profile>> {
+        let name = match &cli_opt.command {
+            CliCommand::Connect {
+                profile: Some(name),
+                ..
+            } => name,
+            _ => return Ok(None),
+        };
+        let path = match &cli_opt.config_path {
+            Some(path) => PathBuf::from(path),
+            None => Config::default_path().ok_or_else(|| {
+                IcmtError::Usage("no configuration directory; use --config".to_string())
+            })?,
+        };
+        let config = Config::load(&path)?;
+        match config.profiles.get(name) {
+            Some(profile) => Ok(Some(profile.clone())),
+            None => Err(IcmtError::Usage(format!(
+                "{}: no profile named {:?} (profiles: {})",
+                path.display(),
+                name,
+                config
+                    .profiles
+                    .keys()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))),
+        }
+    }
+}
+
+impl Profile {
+    /// Fill in the options that the command line leaves unset.
+    pub fn apply(&self, cli_opt: &mut CliOpt, matches: &clap::ArgMatches) -> IcmtResult<()> {
+        if matches.occurrences_of("capture_output_path") == 0 {
+            if let Some(path) = &self.capture.output_path {
+                cli_opt.capture_output_path = path.clone();
+            }
+        }
+        if matches.occurrences_of("engiffen_frame_rate") == 0 {
+            if let Some(rate) = self.capture.engiffen_framerate {
+                cli_opt.engiffen_frame_rate = rate;
+            }
+        }
+        // (either flag, or its counterpart, overrides the profile.)
+        if matches.occurrences_of("no_capture") + matches.occurrences_of("capture") == 0 {
+            if let Some(no_capture) = self.capture.no_capture {
+                cli_opt.no_capture = no_capture;
+            }
+        }
+        if matches.occurrences_of("all_graphics") + matches.occurrences_of("no_all_graphics") == 0 {
+            if let Some(all_graphics) = self.capture.all_graphics {
+                cli_opt.all_graphics = all_graphics;
+            }
+        }
+        if matches.occurrences_of("view_method") == 0 {
+            if let Some(method) = &self.view_method {
+                cli_opt.view_method = method.clone();
+            }
+        }
+        if matches.occurrences_of("update_method") == 0 {
+            if let Some(method) = &self.update_method {
+                cli_opt.update_method = method.clone();
+            }
+        }
+        if cli_opt.extra_arg.is_none() {
+            cli_opt.extra_arg = self.extra_arg.clone();
+        }
+        if cli_opt.identity_pem.is_none() {
+            cli_opt.identity_pem = self.identity.clone();
+        }
+        if !(cli_opt.log_trace || cli_opt.log_debug || cli_opt.log_info) {
+            match self.log_level.as_ref().map(|l| l.as_str()) {
+                None | Some("warn") => (),
+                Some("info") => cli_opt.log_info = true,
+                Some("debug") => cli_opt.log_debug = true,
+                Some("trace") => cli_opt.log_trace = true,
+                Some(level) => {
+                    return Err(IcmtError::Usage(format!(
+                        "unknown log_level {:?} (expected trace, debug, info or warn)",
+                        level
+                    )))
+                }
+            }
+        }
+        if let CliCommand::Connect {
+            replica_url,
+            canister_id,
+            user,
+            ..
+        } = &mut cli_opt.command
+        {
+            if replica_url.is_none() {
+                *replica_url = self.replica_url.clone();
+            }
+            if canister_id.is_none() {
+                *canister_id = self.canister_id.clone();
+            }
+            // The profile's user applies unless the command line names one.
+            if let Some(p) = &self.user {
+                if user.user_info_text.is_none()
+                    && user.user_file.is_none()
+                    && user.user_name.is_none()
+                {
+                    user.user_name = Some(p.name.clone());
+                    user.fg = user.fg.or(Some(Rgb(p.fg.0, p.fg.1, p.fg.2)));
+                    user.bg = user.bg.or(Some(Rgb(p.bg.0, p.bg.1, p.bg.2)));
+                    user.avatar = user.avatar.clone().or(p.avatar.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+}
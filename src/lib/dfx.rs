@@ -0,0 +1,235 @@
+//! Canister names and replica URLs, from `dfx` project files.
+//!
+//! Within a `dfx` project (a directory, or a parent directory, with a
+//! `dfx.json` file), canisters may be named instead of giving their
+//! principal ids: names resolve through `.dfx/<network>/canister_ids.json`
+//! and (for other networks than `local`) through `canister_ids.json`.
+
+use ic_types::Principal;
+use log::info;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+use crate::error::{io_err, IcmtError, IcmtResult};
+
+/// URL of the IC network, named `ic` by dfx.
+const IC_NETWORK_URL: &str = "https://ic0.app";
+
+/// The dfx project directory enclosing the current directory, if any.
+pub fn project_dir() -> Option<PathBuf> {
+    let mut dir = std::env::current_dir().ok()?;
+    loop {
+        if dir.join("dfx.json").is_file() {
+            return Some(dir);
+        }
+        if !dir.pop() {
+            return None;
+        }
+    }
+}
+
+fn read_json(path: &Path) -> IcmtResult<Value> {
+    let text = std::fs::read_to_string(path).map_err(io_err(path))?;
+    serde_json::from_str(&text)
+        .map_err(|e| IcmtError::Usage(format!("{}: invalid JSON: {}", path.display(), e)))
+}
+
+/// Resolve a canister, given by principal id or by name, to its principal id.
+pub fn resolve_canister(name_or_id: &str, network: &str) -> IcmtResult<Principal> {
+    resolve_canister_in(project_dir().as_deref(), name_or_id, network)
+}
+
+/// Resolve a canister, as `resolve_canister` does, in the given project directory.
+fn resolve_canister_in(
+    dir: Option<&Path>,
+    name_or_id: &str,
+    network: &str,
+) -> IcmtResult<Principal> {
+    if let Ok(principal) = Principal::from_text(name_or_id) {
+        return Ok(principal);
+    }
+    let dir = dir.ok_or_else(|| {
+        IcmtError::Usage(format!(
+            "{:?} is not a canister id, and there is no dfx project (dfx.json) to name it",
+            name_or_id
+        ))
+    })?;
+    let mut candidates = vec![dir.join(".dfx").join(network).join("canister_ids.json")];
+    if network != "local" {
+        candidates.push(dir.join("canister_ids.json"));
+    }
+    for path in candidates.iter().filter(|p| p.is_file()) {
+        let ids = read_json(path)?;
+        if let Some(id) = ids[name_or_id][network].as_str() {
+            info!(
+                "Canister {} on network {} is {} (from {})",
+                name_or_id,
+                network,
+                id,
+                path.display()
+            );
+            return Principal::from_text(id).map_err(|e| {
+                IcmtError::Usage(format!(
+                    "{}: invalid id for canister {}: {}",
+                    path.display(),
+                    name_or_id,
+                    e
+                ))
+            });
+        }
+    }
+    Err(IcmtError::Usage(format!(
+        "no canister named {:?} on network {:?} (looked in {}); is it created?",
+        name_or_id,
+        network,
+        candidates
+            .iter()
+            .map(|p| p.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    )))
+}
+
+/// The replica URL for a network, from the enclosing project's `dfx.json`.
+pub fn replica_url(network: &str) -> IcmtResult<Option<String>> {
+    replica_url_in(project_dir().as_deref(), network)
+}
+
+/// The replica URL for a network, as `replica_url` gives it, in the given project directory.
+fn replica_url_in(dir: Option<&Path>, network: &str) -> IcmtResult<Option<String>> {
+    if network == "ic" {
+        return Ok(Some(IC_NETWORK_URL.to_string()));
+    }
+    let dir = match dir {
+        Some(dir) => dir,
+        None => return Ok(None),
+    };
+    let dfx = read_json(&dir.join("dfx.json"))?;
+    let net = &dfx["networks"][network];
+    if let Some(provider) = net["providers"][0].as_str() {
+        return Ok(Some(with_scheme(provider)));
+    }
+    if let Some(bind) = net["bind"].as_str() {
+        return Ok(Some(with_scheme(bind)));
+    }
+    if network == "local" {
+        let start = &dfx["defaults"]["start"];
+        let address = start["address"].as_str().unwrap_or("127.0.0.1");
+        if let Some(port) = start["port"].as_u64() {
+            return Ok(Some(format!("http://{}:{}", address, port)));
+        }
+        return Ok(Some(format!("http://{}:8000", address)));
+    }
+    Ok(None)
+}
+
+fn with_scheme(address: &str) -> String {
+    if address.contains("://") {
+        address.to_string()
+    } else {
+        format!("http://{}", address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const ID: &str = "rwlgt-iiaaa-aaaaa-aaaaa-cai";
+    const IC_ID: &str = "ryjl3-tyaaa-aaaaa-aaaba-cai";
+
+    /// A dfx project directory (removed when dropped), with the given `dfx.json`.
+    struct Project(PathBuf);
+
+    impl Project {
+        fn new(name: &str, dfx_json: &str) -> Project {
+            let name = format!("icmt-dfx-{}-{}", name, std::process::id());
+            let dir = std::env::temp_dir().join(name);
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(dir.join(".dfx").join("local")).unwrap();
+            fs::write(dir.join("dfx.json"), dfx_json).unwrap();
+            Project(dir)
+        }
+
+        fn write(&self, path: &str, text: &str) {
+            fs::write(self.0.join(path), text).unwrap();
+        }
+    }
+
+    impl Drop for Project {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn usage(result: IcmtResult<impl std::fmt::Debug>) -> String {
+        match result {
+            Err(IcmtError::Usage(message)) => message,
+            other => panic!("expected a usage error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn resolves_canister_names() {
+        let project = Project::new("names", "{}");
+        let dir = Some(project.0.as_path());
+        project.write(
+            ".dfx/local/canister_ids.json",
+            &format!(r#"{{"terminal": {{"local": "{}"}}}}"#, ID),
+        );
+        project.write(
+            "canister_ids.json",
+            &format!(r#"{{"terminal": {{"ic": "{}"}}}}"#, IC_ID),
+        );
+        let resolve = |name, network| resolve_canister_in(dir, name, network);
+        assert_eq!(resolve("terminal", "local").unwrap().to_text(), ID);
+        assert_eq!(resolve("terminal", "ic").unwrap().to_text(), IC_ID);
+        // Ids need no project.
+        assert_eq!(resolve_canister_in(None, ID, "local").unwrap().to_text(), ID);
+        assert!(usage(resolve("other", "local")).contains("no canister named"));
+        assert!(usage(resolve_canister_in(None, "terminal", "local")).contains("no dfx project"));
+
+        project.write(
+            ".dfx/local/canister_ids.json",
+            r#"{"terminal": {"local": "not an id"}}"#,
+        );
+        assert!(usage(resolve("terminal", "local")).contains("invalid id"));
+        project.write(".dfx/local/canister_ids.json", "{");
+        assert!(usage(resolve("terminal", "local")).contains("invalid JSON"));
+    }
+
+    #[test]
+    fn replica_urls() {
+        assert_eq!(
+            replica_url_in(None, "ic").unwrap(),
+            Some(IC_NETWORK_URL.to_string())
+        );
+        assert_eq!(replica_url_in(None, "local").unwrap(), None);
+
+        let project = Project::new("default", "{}");
+        let dir = Some(project.0.as_path());
+        assert_eq!(
+            replica_url_in(dir, "local").unwrap(),
+            Some("http://127.0.0.1:8000".to_string())
+        );
+        assert_eq!(replica_url_in(dir, "staging").unwrap(), None);
+
+        let project = Project::new(
+            "networks",
+            r#"{
+                "defaults": {"start": {"address": "0.0.0.0", "port": 4943}},
+                "networks": {
+                    "staging": {"providers": ["https://staging.example.org"]},
+                    "testnet": {"bind": "10.0.0.1:8080"}
+                }
+            }"#,
+        );
+        let dir = Some(project.0.as_path());
+        let url = |network| replica_url_in(dir, network).unwrap();
+        assert_eq!(url("local"), Some("http://0.0.0.0:4943".to_string()));
+        assert_eq!(url("staging"), Some("https://staging.example.org".to_string()));
+        assert_eq!(url("testnet"), Some("http://10.0.0.1:8080".to_string()));
+        assert_eq!(url("ic"), Some(IC_NETWORK_URL.to_string()));
+    }
+}
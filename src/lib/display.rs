@@ -0,0 +1,299 @@
+//! Display lists: graphics results, compiled for drawing.
+//!
+//! A graphics result is a tree of elements, with (unbounded) natural
+//! numbers for positions, sizes and colors.  Compiling it resolves each
+//! element's offset, saturates its numbers (rather than panicking on
+//! large ones), clips it to the frame, and gives a flat list of filled
+//! rectangles in native (window) coordinates, drawn in order.
+
+use log::{error, warn};
+use num_traits::cast::ToPrimitive;
+
+use crate::handshake::Capabilities;
+use crate::types::{
+    byte_ceil,
+    graphics::{self, Elm, Fill},
+    Nat,
+};
+
+/// Largest coordinate (or size) in a display list; SDL's own limit.
+pub const MAX_COORD: i64 = (i32::MAX / 2) as i64;
+
+/// A filled rectangle, in window coordinates, clipped to the frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FillRect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub color: (u8, u8, u8),
+}
+
+/// A compiled frame: its rectangles, in drawing order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DisplayList {
+    pub rects: Vec<FillRect>,
+}
+
+/// A natural number, saturated to a coordinate.
+fn coord(n: &Nat) -> i64 {
+    n.0.to_i64().unwrap_or(MAX_COORD).min(MAX_COORD)
+}
+
+fn color(c: &graphics::Color) -> (u8, u8, u8) {
+    (byte_ceil(&c.0), byte_ceil(&c.1), byte_ceil(&c.2))
+}
+
+struct Compiler<'a> {
+    /// The frame's size (the clipping bound).
+    width: i64,
+    height: i64,
+    rects: Vec<FillRect>,
+    /// The negotiated capabilities, if any: elements of other kinds are skipped.
+    caps: Option<&'a Capabilities>,
+    /// Kinds of the elements skipped.
+    skipped: Vec<&'static str>,
+}
+
+impl Compiler<'_> {
+    /// Whether the element's kind was negotiated; if not, it is skipped.
+    fn supports(&mut self, elm: &Elm) -> bool {
+        match self.caps {
+            Some(caps) if !caps.supports_elm(elm) => {
+                if !self.skipped.contains(&elm.kind()) {
+                    self.skipped.push(elm.kind());
+                }
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Fill a rectangle (with its corner at `(x, y)`), clipped to the frame.
+    fn fill(&mut self, x: i64, y: i64, w: i64, h: i64, color: (u8, u8, u8)) {
+        let (x0, y0) = (x.min(self.width), y.min(self.height));
+        let x1 = x.saturating_add(w).min(self.width);
+        let y1 = y.saturating_add(h).min(self.height);
+        if x1 <= x0 || y1 <= y0 {
+            return;
+        }
+        self.rects.push(FillRect {
+            x: x0 as i32,
+            y: y0 as i32,
+            width: (x1 - x0) as u32,
+            height: (y1 - y0) as u32,
+            color,
+        })
+    }
+
+    /// A rectangle's fill: closed, or its (one-pixel) outline as four edges.
+    fn rect(&mut self, x: i64, y: i64, w: i64, h: i64, fill: &Fill) {
+        match fill {
+            Fill::None => (),
+            Fill::Closed(c) => self.fill(x, y, w, h, color(c)),
+            Fill::Open(c, _) => {
+                let c = color(c);
+                if w <= 2 || h <= 2 {
+                    self.fill(x, y, w, h, c);
+                } else {
+                    let (right, bottom) = (x.saturating_add(w - 1), y.saturating_add(h - 1));
+                    let y_inner = y.saturating_add(1);
+                    self.fill(x, y, w, 1, c);
+                    self.fill(x, bottom, w, 1, c);
+                    self.fill(x, y_inner, 1, h - 2, c);
+                    self.fill(right, y_inner, 1, h - 2, c);
+                }
+            }
+        }
+    }
+
+    /// An element (and those within it), in order.
+    ///
+    /// Walks the tree without recursion, so deep ones cannot overflow the stack.
+    fn elm(&mut self, elm: &Elm) {
+        let mut stack = vec![(0i64, 0i64, elm)];
+        while let Some((x, y, elm)) = stack.pop() {
+            if !self.supports(elm) {
+                continue;
+            }
+            match elm {
+                Elm::Rect(r, fill) => {
+                    let x = x.saturating_add(coord(&r.pos.x));
+                    let y = y.saturating_add(coord(&r.pos.y));
+                    self.rect(x, y, coord(&r.dim.width), coord(&r.dim.height), fill)
+                }
+                Elm::Node(node) => {
+                    let x = x.saturating_add(coord(&node.rect.pos.x));
+                    let y = y.saturating_add(coord(&node.rect.pos.y));
+                    let (w, h) = (coord(&node.rect.dim.width), coord(&node.rect.dim.height));
+                    self.rect(x, y, w, h, &node.fill);
+                    stack.extend(node.elms.iter().rev().map(|elm| (x, y, elm)));
+                }
+            }
+        }
+    }
+}
+
+impl DisplayList {
+    /// Compile a graphics result, for a frame of the given dimension.
+    ///
+    /// The frame's background is black; an error result has no rectangles.
+    pub fn compile(dim: &graphics::Dim, rr: &graphics::Result) -> DisplayList {
+        Self::compile_with(dim, rr, None)
+    }
+
+    /// Compile a graphics result, as `compile` does, skipping outputs and
+    /// elements (with those within them) of kinds that the canister did
+    /// not name in its handshake.
+    pub fn compile_for(
+        dim: &graphics::Dim,
+        rr: &graphics::Result,
+        caps: &Capabilities,
+    ) -> DisplayList {
+        Self::compile_with(dim, rr, Some(caps))
+    }
+
+    fn compile_with(
+        dim: &graphics::Dim,
+        rr: &graphics::Result,
+        caps: Option<&Capabilities>,
+    ) -> DisplayList {
+        let mut c = Compiler {
+            width: coord(&dim.width),
+            height: coord(&dim.height),
+            rects: vec![],
+            caps,
+            skipped: vec![],
+        };
+        if let (Some(caps), graphics::Result::Ok(out)) = (caps, rr) {
+            if !caps.supports_out(out) {
+                warn!(
+                    "skipping {:?} graphics, which the canister did not name",
+                    out.kind()
+                );
+                return DisplayList::default();
+            }
+        }
+        let elm = match rr {
+            graphics::Result::Ok(graphics::Out::Draw(elm)) => elm,
+            graphics::Result::Ok(graphics::Out::Redraw(elms)) => {
+                if elms.len() == 1 && elms[0].0 == "screen" {
+                    &elms[0].1
+                } else {
+                    warn!("unrecognized redraw elements {:?}", elms);
+                    return DisplayList::default();
+                }
+            }
+            graphics::Result::Err(opt_message) => {
+                match opt_message {
+                    None => error!("Error result from server. No message."),
+                    Some(ref m) => error!("Error message from server: {}", m),
+                };
+                return DisplayList::default();
+            }
+        };
+        c.fill(0, 0, c.width, c.height, (0, 0, 0));
+        c.elm(elm);
+        if !c.skipped.is_empty() {
+            warn!(
+                "skipping {:?} elements, which the canister did not name",
+                c.skipped
+            );
+        }
+        DisplayList { rects: c.rects }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::graphics::{Node, Out, Rect};
+
+    fn nat(n: u64) -> Nat {
+        Nat::from(n)
+    }
+
+    fn rect(x: u64, y: u64) -> Elm {
+        Elm::Rect(
+            Rect::new(nat(x), nat(y), nat(2), nat(2)),
+            Fill::Closed((nat(255), nat(0), nat(0))),
+        )
+    }
+
+    fn fill_rect(x: i32, y: i32, width: u32, height: u32, color: (u8, u8, u8)) -> FillRect {
+        FillRect {
+            x,
+            y,
+            width,
+            height,
+            color,
+        }
+    }
+
+    fn dim() -> graphics::Dim {
+        graphics::Dim {
+            width: nat(10),
+            height: nat(10),
+        }
+    }
+
+    fn strings(kinds: &[&str]) -> Vec<String> {
+        kinds.iter().map(|k| k.to_string()).collect()
+    }
+
+    fn caps(out_kinds: &[&str], elm_kinds: &[&str]) -> Capabilities {
+        Capabilities {
+            out_kinds: strings(out_kinds),
+            elm_kinds: strings(elm_kinds),
+            ..Capabilities::all()
+        }
+    }
+
+    /// A rectangle, and a node (without fill) with another one in it.
+    fn frame() -> graphics::Result {
+        graphics::Result::Ok(Out::Draw(Elm::Node(Box::new(Node {
+            rect: Rect::new(nat(0), nat(0), nat(10), nat(10)),
+            fill: Fill::None,
+            elms: vec![
+                rect(1, 1),
+                Elm::Node(Box::new(Node {
+                    rect: Rect::new(nat(4), nat(4), nat(4), nat(4)),
+                    fill: Fill::None,
+                    elms: vec![rect(1, 1)],
+                })),
+            ],
+        }))))
+    }
+
+    #[test]
+    fn compiles_negotiated_kinds() {
+        let black = fill_rect(0, 0, 10, 10, (0, 0, 0));
+        let list = DisplayList::compile(&dim(), &frame());
+        assert_eq!(
+            list.rects,
+            vec![
+                black,
+                fill_rect(1, 1, 2, 2, (255, 0, 0)),
+                fill_rect(5, 5, 2, 2, (255, 0, 0)),
+            ]
+        );
+        let all = caps(&["draw", "redraw"], &["rect", "node"]);
+        assert_eq!(DisplayList::compile_for(&dim(), &frame(), &all), list);
+    }
+
+    #[test]
+    fn skips_kinds_not_negotiated() {
+        let black = fill_rect(0, 0, 10, 10, (0, 0, 0));
+        // Without nodes, nothing within them is drawn.
+        let rects = caps(&["draw"], &["rect"]);
+        let list = DisplayList::compile_for(&dim(), &frame(), &rects);
+        assert_eq!(list.rects, vec![black]);
+        let draw_rect = graphics::Result::Ok(Out::Draw(rect(1, 1)));
+        let list = DisplayList::compile_for(&dim(), &draw_rect, &rects);
+        assert_eq!(list.rects, vec![black, fill_rect(1, 1, 2, 2, (255, 0, 0))]);
+        // Nor are outputs of other kinds.
+        let redraws = caps(&["redraw"], &["rect", "node"]);
+        let list = DisplayList::compile_for(&dim(), &draw_rect, &redraws);
+        assert_eq!(list, DisplayList::default());
+    }
+}
@@ -1,21 +1,180 @@
 //! Errors generated from the mini terminal.
+//!
+//! Each error keeps its underlying cause (see `std::error::Error::source`),
+//! and maps to a distinct process exit code (see `IcmtError::exit_code`).
 
-use log::error;
+use log::debug;
+use std::sync::Arc;
 
 /// Result from mini terminal.
 pub type IcmtResult<X> = Result<X, IcmtError>;
 
+/// Process exit codes, one per kind of error.
+pub mod exit_code {
+    /// Some other error.
+    pub const OTHER: i32 = 1;
+    /// Invalid command-line arguments (or configuration).
+    pub const USAGE: i32 = 2;
+    /// File (or other I/O) error, e.g., a missing file.
+    pub const IO: i32 = 3;
+    /// Cannot reach the replica: transport failures and timeouts.
+    pub const AGENT: i32 = 4;
+    /// The canister (or replica) rejected a call.
+    pub const REJECTED: i32 = 5;
+    /// Cannot encode or decode Candid (or hex) data.
+    pub const DECODE: i32 = 6;
+    /// Cannot produce captured output (GIF video).
+    pub const CAPTURE: i32 = 7;
+    /// The canister's interface is incompatible with the terminal.
+    pub const INTERFACE: i32 = 8;
+}
+
 /// Errors from the mini terminal, or its subcomponents.
 #[derive(Debug, Clone)]
 pub enum IcmtError {
-    Candid(std::sync::Arc<candid::Error>),
-    Agent(), /* Clone => Agent(ic_agent::AgentError) */
+    /// Invalid command-line arguments (or configuration).
+    Usage(String),
+    /// Candid encoding or decoding failed.
+    Candid(Arc<candid::Error>),
+    /// Decoding the named data (e.g., a `view` response) failed.
+    Decode {
+        what: String,
+        source: Arc<candid::Error>,
+    },
+    /// The IC agent failed, or the canister rejected a call.
+    Agent(Arc<ic_agent::AgentError>),
+    /// I/O failed, at the given path (when known).
+    Io {
+        path: Option<String>,
+        source: Arc<std::io::Error>,
+    },
+    /// The canister's interface differs from the terminal's (each difference).
+    Interface(Vec<String>),
+    /// A channel between the main loop and a service task has closed.
+    Channel(String),
     String(String),
-    Engiffen(), /* Clone => engiffen::Error */
+    Engiffen(Arc<engiffen::Error>),
     RingKeyRejected(ring::error::KeyRejected),
     RingUnspecified(ring::error::Unspecified),
     FromHexError(hex::FromHexError),
 }
+
+impl IcmtError {
+    /// Reject code and message, when the canister (or replica) rejected a call.
+    pub fn reject(&self) -> Option<(u64, &str)> {
+        match self {
+            IcmtError::Agent(ae) => match &**ae {
+                ic_agent::AgentError::ReplicaError {
+                    reject_code,
+                    reject_message,
+                } => Some((*reject_code, reject_message.as_str())),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Would retrying the failed operation possibly succeed?
+    ///
+    /// Transport failures, timeouts and server errors (HTTP 5xx) are
+    /// transient; rejected calls, client errors (HTTP 4xx), malformed URLs,
+    /// certificate and identity errors, decoding and other local errors
+    /// would only fail again.
+    pub fn is_transient(&self) -> bool {
+        use ic_agent::AgentError;
+        match self {
+            IcmtError::Agent(ae) => match &**ae {
+                AgentError::TransportError(_) | AgentError::TimeoutWaitingForResponse() => true,
+                AgentError::HttpError(payload) => payload.status >= 500,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Process exit code for this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            IcmtError::Usage(_) => exit_code::USAGE,
+            IcmtError::Io { .. } => exit_code::IO,
+            IcmtError::Agent(_) if self.reject().is_some() => exit_code::REJECTED,
+            IcmtError::Agent(_) => exit_code::AGENT,
+            IcmtError::Candid(_) | IcmtError::Decode { .. } | IcmtError::FromHexError(_) => {
+                exit_code::DECODE
+            }
+            IcmtError::Engiffen(_) => exit_code::CAPTURE,
+            IcmtError::Interface(_) => exit_code::INTERFACE,
+            IcmtError::Channel(_)
+            | IcmtError::String(_)
+            | IcmtError::RingKeyRejected(_)
+            | IcmtError::RingUnspecified(_) => exit_code::OTHER,
+        }
+    }
+}
+
+impl std::fmt::Display for IcmtError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IcmtError::Usage(m) => write!(f, "{}", m),
+            IcmtError::Candid(e) => write!(f, "Candid error: {}", e),
+            IcmtError::Decode { what, source } => {
+                write!(f, "cannot decode {}: {}", what, source)
+            }
+            IcmtError::Agent(_) if self.reject().is_some() => {
+                let (code, message) = self.reject().unwrap();
+                write!(f, "call rejected (code {}): {}", code, message)
+            }
+            IcmtError::Agent(e) => write!(f, "agent error: {}", e),
+            IcmtError::Io {
+                path: Some(path),
+                source,
+            } => write!(f, "{}: {}", path, source),
+            IcmtError::Io { path: None, source } => write!(f, "I/O error: {}", source),
+            IcmtError::Interface(diffs) => {
+                write!(f, "canister interface is incompatible:")?;
+                for d in diffs.iter() {
+                    write!(f, "\n  - {}", d)?;
+                }
+                Ok(())
+            }
+            IcmtError::Channel(m) => write!(f, "task channel closed: {}", m),
+            IcmtError::String(m) => write!(f, "{}", m),
+            IcmtError::Engiffen(e) => write!(f, "cannot write GIF: {:?}", e),
+            IcmtError::RingKeyRejected(e) => write!(f, "key rejected: {}", e),
+            IcmtError::RingUnspecified(e) => write!(f, "cryptography error: {}", e),
+            IcmtError::FromHexError(e) => write!(f, "invalid hex data: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for IcmtError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            IcmtError::Candid(e) => Some(&**e),
+            IcmtError::Decode { source, .. } => Some(&**source),
+            IcmtError::Agent(e) => Some(&**e),
+            IcmtError::Io { source, .. } => Some(&**source),
+            IcmtError::RingKeyRejected(e) => Some(e),
+            IcmtError::RingUnspecified(e) => Some(e),
+            IcmtError::FromHexError(e) => Some(e),
+            IcmtError::Usage(_)
+            | IcmtError::Interface(_)
+            | IcmtError::Channel(_)
+            | IcmtError::String(_)
+            | IcmtError::Engiffen(_) => None,
+        }
+    }
+}
+
+/// Attach a path to I/O errors; for example, `File::open(p).map_err(io_err(p))`.
+pub fn io_err<P: AsRef<std::path::Path>>(path: P) -> impl FnOnce(std::io::Error) -> IcmtError {
+    let path = path.as_ref().display().to_string();
+    move |e| IcmtError::Io {
+        path: Some(path),
+        source: Arc::new(e),
+    }
+}
+
 impl std::convert::From<hex::FromHexError> for IcmtError {
     fn from(fhe: hex::FromHexError) -> Self {
         IcmtError::FromHexError(fhe)
@@ -24,31 +183,28 @@ impl std::convert::From<hex::FromHexError> for IcmtError {
 
 impl std::convert::From<ic_agent::AgentError> for IcmtError {
     fn from(ae: ic_agent::AgentError) -> Self {
-        error!("Detected agent error: {:?}", ae);
-        /*IcmtError::Agent(ae)*/
-        IcmtError::Agent()
+        debug!("Detected agent error: {:?}", ae);
+        IcmtError::Agent(Arc::new(ae))
     }
 }
 
 impl std::convert::From<candid::Error> for IcmtError {
     fn from(e: candid::Error) -> Self {
-        IcmtError::Candid(std::sync::Arc::new(e))
+        IcmtError::Candid(Arc::new(e))
     }
 }
 
-impl<T> std::convert::From<std::sync::mpsc::SendError<T>> for IcmtError {
-    fn from(_s: std::sync::mpsc::SendError<T>) -> Self {
-        IcmtError::String("send error".to_string())
-    }
-}
-impl std::convert::From<std::sync::mpsc::RecvError> for IcmtError {
-    fn from(_s: std::sync::mpsc::RecvError) -> Self {
-        IcmtError::String("recv error".to_string())
+impl<T> std::convert::From<tokio::sync::mpsc::error::SendError<T>> for IcmtError {
+    fn from(_s: tokio::sync::mpsc::error::SendError<T>) -> Self {
+        IcmtError::Channel("send error".to_string())
     }
 }
 impl std::convert::From<std::io::Error> for IcmtError {
-    fn from(_s: std::io::Error) -> Self {
-        IcmtError::String("IO error".to_string())
+    fn from(e: std::io::Error) -> Self {
+        IcmtError::Io {
+            path: None,
+            source: Arc::new(e),
+        }
     }
 }
 impl std::convert::From<String> for IcmtError {
@@ -67,7 +223,7 @@ impl std::convert::From<ring::error::Unspecified> for IcmtError {
     }
 }
 impl std::convert::From<engiffen::Error> for IcmtError {
-    fn from(_e: engiffen::Error) -> Self {
-        IcmtError::Engiffen()
+    fn from(e: engiffen::Error) -> Self {
+        IcmtError::Engiffen(Arc::new(e))
     }
 }
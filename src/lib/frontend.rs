@@ -0,0 +1,143 @@
+//! Front ends of a session: local input, graphics output and captures.
+//!
+//! A session takes its input from an `InputSource`, shows the canister's
+//! graphics with a `Renderer` and records them with each `CaptureSink`.
+//! The `sdl` module gives the (windowed) front end of `ic-mt`, and the `tty`
+//! module its text-terminal one; `Headless` does without one.
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use crate::error::IcmtResult;
+use crate::handshake::Capabilities;
+use crate::metrics::Summary;
+use crate::types::{event, graphics, ConnectionStatus};
+
+/// Local input, from the user (or a program, on their behalf).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Input {
+    /// An event, for the canister.
+    Event(event::Event),
+    /// Show (or hide) the heads-up display.
+    ToggleHud,
+    /// Show the last frame again (e.g., after the window was resized).
+    Redraw,
+}
+
+/// Wakes a session's main loop (from any thread), e.g., when a service task responds.
+pub type Waker = Arc<dyn Fn() + Send + Sync>;
+
+/// Source of local input.
+///
+/// The session's main loop waits for input, so its input source must also
+/// wake it for other reasons (responses from its tasks, say).
+pub trait InputSource {
+    /// Next input, waiting at most `timeout` for it; `None` once woken (or timed out).
+    fn next_input(&mut self, timeout: Duration) -> IcmtResult<Option<Input>>;
+
+    /// Ends a wait in `next_input` (or the next one, if none is in progress).
+    fn waker(&self) -> Waker;
+}
+
+/// Drawn over the canister's graphics, but never captured.
+pub struct Overlay {
+    pub status: ConnectionStatus,
+    /// Metrics summary, and queue depth (local events not yet acknowledged by an update response).
+    pub hud: Option<(Summary, usize)>,
+}
+
+/// Shows the canister's graphics.
+pub trait Renderer {
+    /// The dimension that the canister draws for (initially).
+    fn dim(&self) -> graphics::Dim;
+
+    /// Draw a frame, with the overlay, and show it.
+    fn render(
+        &mut self,
+        dim: &graphics::Dim,
+        frame: &graphics::Result,
+        overlay: &Overlay,
+    ) -> IcmtResult<()>;
+
+    /// Show the last frame (if any) again, with a new overlay.
+    fn redraw(&mut self, overlay: &Overlay) -> IcmtResult<()>;
+
+    /// The capabilities negotiated with the canister, before the first frame.
+    fn negotiated(&mut self, _caps: &Capabilities) {}
+}
+
+/// Records a session (e.g., as a GIF video).
+pub trait CaptureSink {
+    /// Record a frame.
+    fn frame(&mut self, dim: &graphics::Dim, frame: &graphics::Result) -> IcmtResult<()>;
+
+    /// The capabilities negotiated with the canister, before the first frame.
+    fn negotiated(&mut self, _caps: &Capabilities) {}
+
+    /// The canister's dimension changed, from `old_dim` (for the frames so far).
+    fn resize(&mut self, old_dim: &graphics::Dim, dim: &graphics::Dim) -> IcmtResult<()>;
+
+    /// The session has ended: record its events, and the graphics of its update responses.
+    fn finish(
+        &mut self,
+        dim: &graphics::Dim,
+        events: &Vec<event::EventInfo>,
+        graphics: &Vec<graphics::Result>,
+    ) -> IcmtResult<()>;
+}
+
+/// No input, and no output: for sessions that replay, or that only capture.
+#[derive(Clone, Debug)]
+pub struct Headless {
+    pub dim: graphics::Dim,
+    woken: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl Headless {
+    pub fn new(dim: graphics::Dim) -> Headless {
+        Headless {
+            dim,
+            woken: Arc::new((Mutex::new(false), Condvar::new())),
+        }
+    }
+}
+
+impl InputSource for Headless {
+    fn next_input(&mut self, timeout: Duration) -> IcmtResult<Option<Input>> {
+        let (woken, cvar) = &*self.woken;
+        let woken = woken.lock().unwrap();
+        let (mut woken, _) = cvar
+            .wait_timeout_while(woken, timeout, |woken| !*woken)
+            .unwrap();
+        *woken = false;
+        Ok(None)
+    }
+
+    fn waker(&self) -> Waker {
+        let woken = self.woken.clone();
+        Arc::new(move || {
+            let (flag, cvar) = &*woken;
+            *flag.lock().unwrap() = true;
+            cvar.notify_one();
+        })
+    }
+}
+
+impl Renderer for Headless {
+    fn dim(&self) -> graphics::Dim {
+        self.dim.clone()
+    }
+
+    fn render(
+        &mut self,
+        _dim: &graphics::Dim,
+        _frame: &graphics::Result,
+        _overlay: &Overlay,
+    ) -> IcmtResult<()> {
+        Ok(())
+    }
+
+    fn redraw(&mut self, _overlay: &Overlay) -> IcmtResult<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,248 @@
+//! Protocol version handshake, and capability negotiation.
+//!
+//! Before the first `view`, the terminal calls the (optional) query
+//! `terminalInfo`, advertising the protocol version and the kinds of
+//! events, graphics outputs and graphics elements it supports; the
+//! canister replies with the kinds it uses.  The terminal then only sends
+//! events of kinds that both sides support, and skips graphics of kinds
+//! that the canister did not name (see `DisplayList::compile_for`).
+//! Canisters without `terminalInfo` get the events of the original
+//! protocol (see `Capabilities::legacy`).
+
+use candid::{CandidType, Decode, Deserialize, Encode, Nat};
+use log::{info, warn};
+use num_traits::cast::ToPrimitive;
+
+use crate::session::ConnectCtx;
+use crate::error::IcmtResult;
+use crate::trace::{ArgShape, TraceEntry};
+use crate::types::event::Event;
+use crate::types::graphics;
+
+/// Protocol version of this terminal.
+pub const VERSION: u64 = 1;
+
+/// Name of the handshake query method.
+pub const METHOD: &str = "terminalInfo";
+
+/// Event kinds that this terminal can produce.
+pub const EVENT_KINDS: &[&str] = &[
+    "skip",
+    "quit",
+    "keyDown",
+    "mouseDown",
+    "windowSize",
+    "clipBoard",
+    "tick",
+];
+
+/// Event kinds of the original protocol, before the handshake existed.
+const LEGACY_EVENT_KINDS: &[&str] = &["skip", "quit", "keyDown", "windowSize", "clipBoard"];
+
+/// Event kinds that a canister may not know, when it has no handshake.
+pub fn optional_event_kinds() -> Vec<&'static str> {
+    EVENT_KINDS
+        .iter()
+        .filter(|k| !LEGACY_EVENT_KINDS.contains(k))
+        .cloned()
+        .collect()
+}
+
+/// Graphics output kinds that this terminal can draw.
+pub const OUT_KINDS: &[&str] = &["draw", "redraw"];
+
+/// Graphics element kinds that this terminal can draw.
+pub const ELM_KINDS: &[&str] = &["rect", "node"];
+
+/// Handshake message, in both directions.
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq)]
+pub struct TerminalInfo {
+    pub version: Nat,
+    #[serde(rename = "eventKinds")]
+    pub event_kinds: Vec<String>,
+    /// Absent from the replies of older canisters: they may use any.
+    #[serde(rename = "outKinds")]
+    pub out_kinds: Option<Vec<String>>,
+    #[serde(rename = "elmKinds")]
+    pub elm_kinds: Vec<String>,
+}
+
+/// Negotiated capabilities: what both the terminal and the canister support.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Protocol version of the canister (0 when it has no handshake).
+    pub version: u64,
+    pub event_kinds: Vec<String>,
+    pub out_kinds: Vec<String>,
+    pub elm_kinds: Vec<String>,
+}
+
+fn strings(kinds: &[&str]) -> Vec<String> {
+    kinds.iter().map(|k| k.to_string()).collect()
+}
+
+impl Capabilities {
+    /// Everything this terminal supports (before negotiation).
+    pub fn all() -> Capabilities {
+        Capabilities {
+            version: VERSION,
+            event_kinds: strings(EVENT_KINDS),
+            out_kinds: strings(OUT_KINDS),
+            elm_kinds: strings(ELM_KINDS),
+        }
+    }
+
+    /// The original protocol, for canisters without a handshake.
+    pub fn legacy() -> Capabilities {
+        Capabilities {
+            version: 0,
+            event_kinds: strings(LEGACY_EVENT_KINDS),
+            out_kinds: strings(OUT_KINDS),
+            elm_kinds: strings(ELM_KINDS),
+        }
+    }
+
+    pub fn supports_event(&self, event: &Event) -> bool {
+        self.event_kinds.iter().any(|k| k == event.kind())
+    }
+
+    pub fn supports_out(&self, out: &graphics::Out) -> bool {
+        self.out_kinds.iter().any(|k| k == out.kind())
+    }
+
+    pub fn supports_elm(&self, elm: &graphics::Elm) -> bool {
+        self.elm_kinds.iter().any(|k| k == elm.kind())
+    }
+}
+
+/// This terminal's handshake message.
+pub fn terminal_info() -> TerminalInfo {
+    TerminalInfo {
+        version: Nat::from(VERSION),
+        event_kinds: strings(EVENT_KINDS),
+        out_kinds: Some(strings(OUT_KINDS)),
+        elm_kinds: strings(ELM_KINDS),
+    }
+}
+
+/// Perform the handshake; on rejection (no such method), assume the legacy protocol.
+pub async fn negotiate(ctx: &ConnectCtx) -> IcmtResult<Capabilities> {
+    let arg = Encode!(&terminal_info())?;
+    let response = match &ctx.cfg.trace_player {
+        Some(player) => player.respond(METHOD, &arg, ArgShape::Raw).ok(),
+        None => {
+            let res = ctx
+                .agent
+                .query(&ctx.canister_id, METHOD)
+                .with_arg(arg.clone())
+                .call()
+                .await;
+            match res {
+                Ok(bytes) => Some(bytes),
+                Err(e) => {
+                    let e = crate::error::IcmtError::from(e);
+                    if e.reject().is_none() {
+                        return Err(e);
+                    }
+                    None
+                }
+            }
+        }
+    };
+    let theirs = match response {
+        None => None,
+        Some(bytes) => {
+            if let Some(recorder) = &ctx.cfg.trace_recorder {
+                recorder.record(&TraceEntry {
+                    method: METHOD.to_string(),
+                    arg,
+                    response: bytes.clone(),
+                    elapsed_ms: 0,
+                })?;
+            }
+            Some(Decode!(&bytes, TerminalInfo)?)
+        }
+    };
+    let caps = capabilities(theirs);
+    info!("Negotiated capabilities: {:?}", caps);
+    Ok(caps)
+}
+
+/// The capabilities that both this terminal and the canister (with its
+/// handshake reply, if any) support.
+fn capabilities(theirs: Option<TerminalInfo>) -> Capabilities {
+    let theirs = match theirs {
+        None => {
+            info!("Canister has no {} method; using the legacy protocol.", METHOD);
+            return Capabilities::legacy();
+        }
+        Some(theirs) => theirs,
+    };
+    let ours = Capabilities::all();
+    let theirs_out = theirs.out_kinds.unwrap_or_else(|| ours.out_kinds.clone());
+    let known = |k: &String| {
+        ours.event_kinds.contains(k) || ours.out_kinds.contains(k) || ours.elm_kinds.contains(k)
+    };
+    for k in theirs
+        .event_kinds
+        .iter()
+        .chain(theirs_out.iter())
+        .chain(theirs.elm_kinds.iter())
+    {
+        if !known(k) {
+            warn!(
+                "Canister uses {:?}, which this terminal does not support; ignoring it.",
+                k
+            );
+        }
+    }
+    let both = |ours: Vec<String>, theirs: &[String]| -> Vec<String> {
+        ours.into_iter().filter(|k| theirs.contains(k)).collect()
+    };
+    Capabilities {
+        version: theirs.version.0.to_u64().unwrap_or(u64::MAX),
+        event_kinds: both(ours.event_kinds, &theirs.event_kinds),
+        out_kinds: both(ours.out_kinds, &theirs_out),
+        elm_kinds: both(ours.elm_kinds, &theirs.elm_kinds),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(out_kinds: Option<&[&str]>, elm_kinds: &[&str]) -> TerminalInfo {
+        TerminalInfo {
+            version: Nat::from(1),
+            event_kinds: strings(&["keyDown", "tick", "pinch"]),
+            out_kinds: out_kinds.map(strings),
+            elm_kinds: strings(elm_kinds),
+        }
+    }
+
+    #[test]
+    fn without_handshake_is_legacy() {
+        let caps = capabilities(None);
+        assert_eq!(caps, Capabilities::legacy());
+        assert_eq!(caps.version, 0);
+        assert!(!caps.supports_event(&Event::Tick(Nat::from(0))));
+        assert!(caps.supports_event(&Event::Skip));
+    }
+
+    #[test]
+    fn kinds_that_both_support() {
+        let caps = capabilities(Some(reply(Some(&["draw", "sketch"][..]), &["rect"])));
+        assert_eq!(caps.version, 1);
+        assert_eq!(caps.event_kinds, strings(&["keyDown", "tick"]));
+        assert_eq!(caps.out_kinds, strings(&["draw"]));
+        assert_eq!(caps.elm_kinds, strings(&["rect"]));
+        assert!(!caps.supports_event(&Event::Skip));
+    }
+
+    #[test]
+    fn reply_without_out_kinds_names_them_all() {
+        let caps = capabilities(Some(reply(None, &["rect", "node"])));
+        assert_eq!(caps.out_kinds, strings(OUT_KINDS));
+        assert_eq!(caps.elm_kinds, strings(ELM_KINDS));
+    }
+}
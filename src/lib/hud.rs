@@ -0,0 +1,163 @@
+//! Heads-up display (HUD): latency, payload sizes, queue depth, frame rate
+//! and response-to-present latency.
+//!
+//! Drawn over the window's graphics (never into captures), using a tiny
+//! built-in 3x5 pixel font.
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, RenderTarget};
+
+use crate::metrics::{CallSummary, Summary};
+
+/// Size of each font pixel, in screen pixels.
+const SCALE: i32 = 2;
+/// Margin around the HUD text, in screen pixels.
+const MARGIN: i32 = 6;
+
+/// Glyph rows, top to bottom; the low three bits of each row, left to right.
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        ' ' => [0b000, 0b000, 0b000, 0b000, 0b000],
+        _ => [0b111, 0b101, 0b101, 0b101, 0b111],
+    }
+}
+
+/// Width of text, in screen pixels.
+fn text_width(text: &str) -> i32 {
+    text.chars().count() as i32 * 4 * SCALE
+}
+
+/// Draw a line of text with its top-left corner at (x, y).
+pub fn draw_text<T: RenderTarget>(
+    canvas: &mut Canvas<T>,
+    x: i32,
+    y: i32,
+    text: &str,
+    color: Color,
+) -> Result<(), String> {
+    let mut pixels = vec![];
+    for (i, c) in text.chars().enumerate() {
+        let x0 = x + i as i32 * 4 * SCALE;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..3 {
+                if bits & (0b100 >> col) != 0 {
+                    pixels.push(Rect::new(
+                        x0 + col * SCALE,
+                        y + row as i32 * SCALE,
+                        SCALE as u32,
+                        SCALE as u32,
+                    ));
+                }
+            }
+        }
+    }
+    canvas.set_draw_color(color);
+    canvas.fill_rects(&pixels)
+}
+
+fn bytes_text(bytes: f64) -> String {
+    if bytes >= 1024.0 * 1024.0 {
+        format!("{:.1}MB", bytes / (1024.0 * 1024.0))
+    } else if bytes >= 1024.0 {
+        format!("{:.1}KB", bytes / 1024.0)
+    } else {
+        format!("{:.0}B", bytes)
+    }
+}
+
+fn call_lines(name: &str, s: &CallSummary) -> Vec<String> {
+    vec![
+        format!(
+            "{} N {} P50 {:.0}MS P90 {:.0}MS P99 {:.0}MS",
+            name, s.count, s.p50_ms, s.p90_ms, s.p99_ms
+        ),
+        format!(
+            "{} ARG {} RES {}",
+            name,
+            bytes_text(s.mean_arg_bytes),
+            bytes_text(s.mean_response_bytes)
+        ),
+    ]
+}
+
+/// The HUD's lines of text.
+///
+/// `queue_depth` counts local events not yet acknowledged by an update response.
+pub fn hud_lines(summary: &Summary, queue_depth: usize) -> Vec<String> {
+    let mut lines = call_lines("VIEW", &summary.view);
+    lines.extend(call_lines("UPDT", &summary.update));
+    lines.push(format!("QUEUE {} FPS {:.1}", queue_depth, summary.fps));
+    lines.push(format!(
+        "PRESENT P50 {:.1}MS P99 {:.1}MS",
+        summary.present_p50_ms, summary.present_p99_ms
+    ));
+    lines
+}
+
+/// Draw the HUD (top-left corner); does not present the canvas.
+pub fn draw_hud<T: RenderTarget>(
+    canvas: &mut Canvas<T>,
+    summary: &Summary,
+    queue_depth: usize,
+) -> Result<(), String> {
+    let lines = hud_lines(summary, queue_depth);
+
+    let line_height = 7 * SCALE;
+    let width = lines.iter().map(|l| text_width(l)).max().unwrap_or(0) + 2 * MARGIN;
+    let height = lines.len() as i32 * line_height + 2 * MARGIN - 2 * SCALE;
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.fill_rect(Rect::new(0, 0, width as u32, height as u32))?;
+    canvas.set_draw_color(Color::RGB(0, 255, 0));
+    canvas.draw_rect(Rect::new(0, 0, width as u32, height as u32))?;
+    for (i, line) in lines.iter().enumerate() {
+        draw_text(
+            canvas,
+            MARGIN,
+            MARGIN + i as i32 * line_height,
+            line,
+            Color::RGB(0, 255, 0),
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,321 @@
+//! Interface compatibility check, between a canister and the terminal.
+//!
+//! At startup, the terminal fetches the canister's Candid interface (via
+//! the `__get_candid_interface_tmp_hack` query, which Motoko canisters
+//! provide) and checks that its `view` and `update` methods accept what
+//! the terminal sends, and return what it expects (see `service.did`).
+
+use candid::parser::types::FuncMode;
+use candid::types::{Field, Function, Type};
+use candid::{check_prog, Decode, Encode, IDLProg, TypeEnv};
+use ic_agent::Agent;
+use ic_types::Principal;
+use log::info;
+use std::collections::HashSet;
+
+use crate::error::{IcmtError, IcmtResult};
+
+/// The terminal's own (built-in) view of the service protocol.
+pub const SERVICE_DID: &str = include_str!("../../service.did");
+
+/// Query method that returns a canister's interface (as Candid text).
+const INTERFACE_METHOD: &str = "__get_candid_interface_tmp_hack";
+
+/// Methods that a canister may omit (see `handshake`).
+const OPTIONAL_METHODS: &[&str] = &[crate::handshake::METHOD];
+
+/// Fetch the canister's interface, if it provides it.
+pub async fn fetch_interface(agent: &Agent, canister_id: &Principal) -> IcmtResult<Option<String>> {
+    let res = agent
+        .query(canister_id, INTERFACE_METHOD)
+        .with_arg(Encode!()?)
+        .call()
+        .await;
+    match res {
+        Ok(bytes) => Ok(Some(Decode!(&bytes, String)?)),
+        Err(e) => {
+            let e = IcmtError::from(e);
+            if e.reject().is_some() {
+                // no such method
+                Ok(None)
+            } else {
+                Err(e)
+            }
+        }
+    }
+}
+
+/// A service interface: its type environment, and its methods.
+struct Service {
+    env: TypeEnv,
+    methods: Vec<(String, Type)>,
+}
+
+fn load(did: &str) -> Result<Service, String> {
+    let prog: IDLProg = did.parse().map_err(|e: candid::Error| e.to_string())?;
+    let mut env = TypeEnv::new();
+    let actor = check_prog(&mut env, &prog)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "no service type".to_string())?;
+    let actor = match env.trace_type(&actor).map_err(|e| e.to_string())? {
+        Type::Class(_, t) => env.trace_type(&t).map_err(|e| e.to_string())?,
+        t => t,
+    };
+    match actor {
+        Type::Service(methods) => Ok(Service { env, methods }),
+        t => Err(format!("not a service type: {}", t)),
+    }
+}
+
+impl Service {
+    fn method(&self, name: &str) -> Option<Function> {
+        let ty = self.methods.iter().find(|(n, _)| n == name)?;
+        match self.env.trace_type(&ty.1) {
+            Ok(Type::Func(f)) => Some(f),
+            _ => None,
+        }
+    }
+}
+
+/// Check a canister's interface (as Candid text) against the terminal's.
+///
+/// The canister's view and update methods have the given names, and take
+/// `extra_args` (unchecked) arguments before the terminal's.  On failure,
+/// gives each difference, with its path into the method's types.
+pub fn check_interface(
+    canister_did: &str,
+    view_method: &str,
+    update_method: &str,
+    extra_args: usize,
+) -> Result<(), Vec<String>> {
+    let ours = load(SERVICE_DID).map_err(|e| vec![format!("built-in interface: {}", e)])?;
+    let theirs = load(canister_did).map_err(|e| vec![format!("canister interface: {}", e)])?;
+    let mut diffs = vec![];
+    for (our_name, _) in ours.methods.iter() {
+        let our_f = ours.method(our_name).unwrap();
+        let (name, skip) = match our_name.as_str() {
+            "view" => (view_method, extra_args),
+            "update" => (update_method, extra_args),
+            name => (name, 0),
+        };
+        let mut their_f = match theirs.method(name) {
+            Some(f) => f,
+            None if OPTIONAL_METHODS.contains(&name) => continue,
+            None => {
+                diffs.push(format!("{}: no such method", name));
+                continue;
+            }
+        };
+        if their_f.args.len() < skip {
+            diffs.push(format!(
+                "{}: expected {} extra argument(s), found {} argument(s)",
+                name,
+                skip,
+                their_f.args.len()
+            ));
+            continue;
+        }
+        their_f.args.drain(0..skip);
+        let our_query = our_f.modes.iter().any(|m| matches!(m, FuncMode::Query));
+        let their_query = their_f.modes.iter().any(|m| matches!(m, FuncMode::Query));
+        if our_query && !their_query {
+            diffs.push(format!("{}: expected a query method", name));
+        }
+        let mut check = Check {
+            diffs: &mut diffs,
+            seen: HashSet::new(),
+            optional_cases: crate::handshake::optional_event_kinds(),
+        };
+        // Arguments go from the terminal to the canister...
+        check.sequence(
+            &format!("{} argument", name),
+            (&ours.env, &our_f.args),
+            (&theirs.env, &their_f.args),
+        );
+        // ...and results come back.
+        check.sequence(
+            &format!("{} result", name),
+            (&theirs.env, &their_f.rets),
+            (&ours.env, &our_f.rets),
+        );
+    }
+    if diffs.is_empty() {
+        info!("Canister interface is compatible.");
+        Ok(())
+    } else {
+        Err(diffs)
+    }
+}
+
+/// Structural subtype check, recording differences.
+struct Check<'a> {
+    diffs: &'a mut Vec<String>,
+    /// Pairs of type names already assumed compatible (for recursive types).
+    seen: HashSet<(String, String)>,
+    /// Variant cases that the canister may lack (the terminal negotiates them).
+    optional_cases: Vec<&'static str>,
+}
+
+fn is_optional(env: &TypeEnv, t: &Type) -> bool {
+    match env.trace_type(t) {
+        Ok(Type::Opt(_)) | Ok(Type::Null) | Ok(Type::Reserved) => true,
+        _ => false,
+    }
+}
+
+impl<'a> Check<'a> {
+    /// Check that values of the `sub` sequence can be read as the `sup` sequence.
+    fn sequence(&mut self, path: &str, sub: (&TypeEnv, &Vec<Type>), sup: (&TypeEnv, &Vec<Type>)) {
+        for (i, t2) in sup.1.iter().enumerate() {
+            let path = format!("{} {}", path, i + 1);
+            match sub.1.get(i) {
+                Some(t1) => self.subtype(&path, (sub.0, t1), (sup.0, t2)),
+                None if is_optional(sup.0, t2) => (),
+                None => self.diffs.push(format!("{}: missing; expected {}", path, t2)),
+            }
+        }
+    }
+
+    fn subtype(&mut self, path: &str, sub: (&TypeEnv, &Type), sup: (&TypeEnv, &Type)) {
+        if let (Type::Var(n1), Type::Var(n2)) = (sub.1, sup.1) {
+            if !self.seen.insert((n1.clone(), n2.clone())) {
+                return;
+            }
+        }
+        let (t1, t2) = match (sub.0.trace_type(sub.1), sup.0.trace_type(sup.1)) {
+            (Ok(t1), Ok(t2)) => (t1, t2),
+            (Err(e), _) | (_, Err(e)) => {
+                self.diffs.push(format!("{}: {}", path, e));
+                return;
+            }
+        };
+        match (&t1, &t2) {
+            (_, Type::Reserved) | (Type::Empty, _) => (),
+            (Type::Nat, Type::Int) => (),
+            (Type::Opt(a), Type::Opt(b)) => self.subtype(path, (sub.0, a), (sup.0, b)),
+            (Type::Null, Type::Opt(_)) => (),
+            (_, Type::Opt(b)) => self.subtype(path, (sub.0, &t1), (sup.0, b)),
+            (Type::Vec(a), Type::Vec(b)) => {
+                self.subtype(&format!("{} > vec", path), (sub.0, a), (sup.0, b))
+            }
+            (Type::Record(fs1), Type::Record(fs2)) => {
+                for f2 in fs2.iter() {
+                    let path = format!("{} > field {}", path, f2.id);
+                    match find_field(fs1, f2) {
+                        Some(f1) => self.subtype(&path, (sub.0, &f1.ty), (sup.0, &f2.ty)),
+                        None if is_optional(sup.0, &f2.ty) => (),
+                        None => self.diffs.push(format!("{}: missing field", path)),
+                    }
+                }
+            }
+            (Type::Variant(fs1), Type::Variant(fs2)) => {
+                for f1 in fs1.iter() {
+                    let path = format!("{} > case {}", path, f1.id);
+                    match find_field(fs2, f1) {
+                        Some(f2) => self.subtype(&path, (sub.0, &f1.ty), (sup.0, &f2.ty)),
+                        None if self.optional_cases.contains(&f1.id.to_string().as_str()) => (),
+                        None => self.diffs.push(format!("{}: unexpected case", path)),
+                    }
+                }
+            }
+            (Type::Func(_), Type::Func(_)) | (Type::Service(_), Type::Service(_)) => (),
+            (t1, t2) if t1 == t2 => (),
+            (t1, t2) => self
+                .diffs
+                .push(format!("{}: expected {}, found {}", path, t2, t1)),
+        }
+    }
+}
+
+fn find_field<'b>(fields: &'b [Field], f: &Field) -> Option<&'b Field> {
+    fields.iter().find(|g| g.id.get_id() == f.id.get_id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The terminal's own interface, with a line replaced (or removed).
+    fn edited(line: &str, with: &str) -> String {
+        assert!(SERVICE_DID.contains(line), "no line {:?}", line);
+        SERVICE_DID.replacen(line, with, 1)
+    }
+
+    fn check(did: &str) -> Result<(), Vec<String>> {
+        check_interface(did, "view", "update", 0)
+    }
+
+    #[test]
+    fn compatible() {
+        assert_eq!(check(SERVICE_DID), Ok(()));
+    }
+
+    #[test]
+    fn compatible_superset() {
+        // Another method, and a result record with another field.
+        let did = edited("service : {\n", "service : {\n  reset: () -> ();\n");
+        let did = did.replacen(
+            "type Rect = \n record {\n",
+            "type Rect = \n record {\n   z: nat;\n",
+            1,
+        );
+        assert!(did.contains("z: nat;"));
+        assert_eq!(check(&did), Ok(()));
+    }
+
+    #[test]
+    fn optional_methods_and_cases() {
+        let did = edited("  terminalInfo: (TerminalInfo) -> (TerminalInfo) query;\n", "");
+        assert_eq!(check(&did), Ok(()));
+        let did = edited("   mouseDown: Pos;\n", "");
+        assert_eq!(check(&did), Ok(()));
+    }
+
+    #[test]
+    fn missing_method() {
+        let did = edited(
+            "  update: (vec EventInfo, GraphicsRequest) -> (vec Graphics);\n",
+            "",
+        );
+        assert_eq!(check(&did), Err(vec!["update: no such method".to_string()]));
+    }
+
+    #[test]
+    fn wrong_argument_type() {
+        let did = edited(
+            "  view: (Dim, vec EventInfo) -> (Graphics) query;",
+            "  view: (text, vec EventInfo) -> (Graphics) query;",
+        );
+        let diffs = check(&did).unwrap_err();
+        assert_eq!(diffs.len(), 1, "{:?}", diffs);
+        assert!(diffs[0].starts_with("view argument 1: expected text"), "{:?}", diffs);
+    }
+
+    #[test]
+    fn missing_case() {
+        let did = edited("   keyDown: vec KeyInfo;\n", "");
+        let diffs = check(&did).unwrap_err();
+        assert!(!diffs.is_empty());
+        assert!(
+            diffs.iter().all(|d| d.ends_with("> case keyDown: unexpected case")),
+            "{:?}",
+            diffs
+        );
+    }
+
+    #[test]
+    fn extra_arguments() {
+        let did = edited(
+            "  view: (Dim, vec EventInfo) -> (Graphics) query;",
+            "  view: (text, Dim, vec EventInfo) -> (Graphics) query;",
+        );
+        let did = did.replacen(
+            "  update: (vec EventInfo, GraphicsRequest)",
+            "  update: (text, vec EventInfo, GraphicsRequest)",
+            1,
+        );
+        assert_eq!(check_interface(&did, "view", "update", 1), Ok(()));
+        let diffs = check_interface(SERVICE_DID, "view", "update", 1).unwrap_err();
+        assert!(!diffs.is_empty());
+    }
+}
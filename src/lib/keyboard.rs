@@ -7,6 +7,52 @@ use super::types::event::KeyEventInfo;
 
 use log::info;
 
+/// Keys' characters, and their characters with shift, on a US keyboard.
+const US_SHIFTED: &[(char, char)] = &[
+    ('`', '~'),
+    ('1', '!'),
+    ('2', '@'),
+    ('3', '#'),
+    ('4', '$'),
+    ('5', '%'),
+    ('6', '^'),
+    ('7', '&'),
+    ('8', '*'),
+    ('9', '('),
+    ('0', ')'),
+    ('-', '_'),
+    ('=', '+'),
+    ('[', '{'),
+    (']', '}'),
+    ('\\', '|'),
+    (';', ':'),
+    ('\'', '"'),
+    (',', '<'),
+    ('.', '>'),
+    ('/', '?'),
+];
+
+/// The character that a key types with shift, on a US keyboard.
+pub fn shifted(c: char) -> String {
+    match US_SHIFTED.iter().find(|(key, _)| *key == c) {
+        Some((_, s)) => s.to_string(),
+        None => c.to_uppercase().to_string(),
+    }
+}
+
+/// Does typing this character take shift, on a US keyboard?
+pub fn needs_shift(c: char) -> bool {
+    c.is_uppercase() || US_SHIFTED.iter().any(|(_, s)| *s == c)
+}
+
+fn symbol(c: char, shift: bool) -> String {
+    if shift {
+        shifted(c)
+    } else {
+        c.to_string()
+    }
+}
+
 pub fn translate_event(keycode: &Keycode, keymod: &Mod) -> Option<KeyEventInfo> {
     /* Note: The analysis below encodes my US Mac Book Pro keyboard, almost completely. */
     /* Longer-term, we need a more complex design to handle other mappings and corresponding keyboard variations. */
@@ -23,16 +69,16 @@ pub fn translate_event(keycode: &Keycode, keymod: &Mod) -> Option<KeyEventInfo>
         Keycode::Down => "ArrowDown".to_string(),
         Keycode::Backspace => "Backspace".to_string(),
         Keycode::LShift => "Shift".to_string(),
-        Keycode::Num0 => (if shift { ")" } else { "0" }).to_string(),
-        Keycode::Num1 => (if shift { "!" } else { "1" }).to_string(),
-        Keycode::Num2 => (if shift { "@" } else { "2" }).to_string(),
-        Keycode::Num3 => (if shift { "#" } else { "3" }).to_string(),
-        Keycode::Num4 => (if shift { "$" } else { "4" }).to_string(),
-        Keycode::Num5 => (if shift { "%" } else { "5" }).to_string(),
-        Keycode::Num6 => (if shift { "^" } else { "6" }).to_string(),
-        Keycode::Num7 => (if shift { "&" } else { "7" }).to_string(),
-        Keycode::Num8 => (if shift { "*" } else { "8" }).to_string(),
-        Keycode::Num9 => (if shift { "(" } else { "9" }).to_string(),
+        Keycode::Num0 => symbol('0', shift),
+        Keycode::Num1 => symbol('1', shift),
+        Keycode::Num2 => symbol('2', shift),
+        Keycode::Num3 => symbol('3', shift),
+        Keycode::Num4 => symbol('4', shift),
+        Keycode::Num5 => symbol('5', shift),
+        Keycode::Num6 => symbol('6', shift),
+        Keycode::Num7 => symbol('7', shift),
+        Keycode::Num8 => symbol('8', shift),
+        Keycode::Num9 => symbol('9', shift),
         Keycode::A => (if shift { "A" } else { "a" }).to_string(),
         Keycode::B => (if shift { "B" } else { "b" }).to_string(),
         Keycode::C => (if shift { "C" } else { "c" }).to_string(),
@@ -59,27 +105,27 @@ pub fn translate_event(keycode: &Keycode, keymod: &Mod) -> Option<KeyEventInfo>
         Keycode::X => (if shift { "X" } else { "x" }).to_string(),
         Keycode::Y => (if shift { "Y" } else { "y" }).to_string(),
         Keycode::Z => (if shift { "Z" } else { "z" }).to_string(),
-        Keycode::Equals => (if shift { "+" } else { "=" }).to_string(),
+        Keycode::Equals => symbol('=', shift),
         Keycode::Plus => "+".to_string(),
-        Keycode::Slash => (if shift { "?" } else { "/" }).to_string(),
+        Keycode::Slash => symbol('/', shift),
         Keycode::Question => "?".to_string(),
-        Keycode::Period => (if shift { ">" } else { "." }).to_string(),
+        Keycode::Period => symbol('.', shift),
         Keycode::Greater => ">".to_string(),
-        Keycode::Comma => (if shift { "<" } else { "," }).to_string(),
+        Keycode::Comma => symbol(',', shift),
         Keycode::Less => "<".to_string(),
-        Keycode::Backslash => (if shift { "|" } else { "\\" }).to_string(),
+        Keycode::Backslash => symbol('\\', shift),
         Keycode::Colon => ":".to_string(),
-        Keycode::Semicolon => (if shift { ":" } else { ";" }).to_string(),
+        Keycode::Semicolon => symbol(';', shift),
         Keycode::At => "@".to_string(),
-        Keycode::Minus => (if shift { "_" } else { "-" }).to_string(),
+        Keycode::Minus => symbol('-', shift),
         Keycode::Underscore => "_".to_string(),
         Keycode::Exclaim => "!".to_string(),
         Keycode::Hash => "#".to_string(),
-        Keycode::Backquote => (if shift { "~" } else { "`" }).to_string(),
-        Keycode::Quote => (if shift { "\"" } else { "'" }).to_string(),
+        Keycode::Backquote => symbol('`', shift),
+        Keycode::Quote => symbol('\'', shift),
         Keycode::Quotedbl => "\"".to_string(),
-        Keycode::LeftBracket => (if shift { "{" } else { "[" }).to_string(),
-        Keycode::RightBracket => (if shift { "}" } else { "]" }).to_string(),
+        Keycode::LeftBracket => symbol('[', shift),
+        Keycode::RightBracket => symbol(']', shift),
 
         /* More to consider later (but can we capture these in a browser?):
         Escape --- (Already caught, to quit.)
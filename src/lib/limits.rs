@@ -0,0 +1,601 @@
+//! Resource limits for (untrusted) graphics responses.
+//!
+//! The terminal connects to canisters that it does not control, so it
+//! checks each response before decoding (its size, and how deeply its
+//! values nest) and before drawing or buffering it (its tree's depth and
+//! element count, and its elements' dimensions).  A response over the
+//! limits is rejected: its graphics are replaced by an error result, and
+//! the reason is logged.
+//!
+//! Decoding (and dropping) a graphics tree recurses, once per level, so
+//! the nesting check comes first: it walks the Candid message itself,
+//! without recursion, and without building anything.
+
+use log::warn;
+
+use crate::types::{graphics, Nat};
+
+/// Value nesting (in a Candid message) for each level of graphics
+/// elements: the element (a variant), its node (a record) and the node's
+/// elements (a vector).
+const NESTING_PER_DEPTH: usize = 3;
+
+/// Value nesting around and within the elements: the results (a vector),
+/// each result and its output (variants), and a rectangle's fields.
+const NESTING_SLACK: usize = 8;
+
+/// Limits on each response; see the `--max-*` options.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Limits {
+    pub max_response_bytes: usize,
+    pub max_depth: usize,
+    pub max_elements: usize,
+    pub max_dim: u32,
+    /// Limit on the elements buffered for capture (`--all-graphics`), across responses.
+    pub max_captured_elements: usize,
+}
+
+impl Default for Limits {
+    /// The same defaults as `ic-mt`'s command line.
+    fn default() -> Limits {
+        Limits {
+            max_response_bytes: 2 * 1024 * 1024,
+            max_depth: 64,
+            max_elements: 200_000,
+            max_dim: 16384,
+            max_captured_elements: 1_000_000,
+        }
+    }
+}
+
+impl Limits {
+    /// Check a response's size, before decoding it.
+    pub fn check_bytes(&self, len: usize) -> Result<(), String> {
+        if len > self.max_response_bytes {
+            return Err(format!(
+                "{} bytes, over the limit of {} (--max-response-bytes)",
+                len, self.max_response_bytes
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check how deeply a response's values nest, before decoding it.
+    ///
+    /// The bound follows from `max_depth`; `check_graphics` then checks
+    /// the depth of the decoded elements exactly.
+    pub fn check_nesting(&self, blob: &[u8]) -> Result<(), String> {
+        let max = self
+            .max_depth
+            .saturating_mul(NESTING_PER_DEPTH)
+            .saturating_add(NESTING_SLACK);
+        candid_nesting(blob, max)
+    }
+
+    /// Check the graphics of a response (all of its results, together).
+    ///
+    /// Walks the trees without recursion, so deep ones cannot overflow the stack.
+    pub fn check_graphics(&self, results: &[graphics::Result]) -> Result<(), String> {
+        let max_dim = Nat::from(self.max_dim);
+        let check_dim = |dim: &graphics::Dim| {
+            if dim.width > max_dim || dim.height > max_dim {
+                Err(format!(
+                    "element of size {}x{}, over the limit of {} (--max-dim)",
+                    dim.width, dim.height, self.max_dim
+                ))
+            } else {
+                Ok(())
+            }
+        };
+        let mut stack: Vec<(&graphics::Elm, usize)> = vec![];
+        for result in results.iter() {
+            match result {
+                graphics::Result::Ok(graphics::Out::Draw(elm)) => stack.push((elm, 1)),
+                graphics::Result::Ok(graphics::Out::Redraw(elms)) => {
+                    stack.extend(elms.iter().map(|(_, elm)| (elm, 1)))
+                }
+                graphics::Result::Err(_) => (),
+            }
+        }
+        let too_many = || Err(format!("over {} elements (--max-elements)", self.max_elements));
+        let mut count = stack.len();
+        if count > self.max_elements {
+            return too_many();
+        }
+        while let Some((elm, depth)) = stack.pop() {
+            if depth > self.max_depth {
+                return Err(format!(
+                    "elements nested over {} deep (--max-depth)",
+                    self.max_depth
+                ));
+            }
+            match elm {
+                graphics::Elm::Rect(r, _) => check_dim(&r.dim)?,
+                graphics::Elm::Node(node) => {
+                    check_dim(&node.rect.dim)?;
+                    count += node.elms.len();
+                    if count > self.max_elements {
+                        return too_many();
+                    }
+                    stack.extend(node.elms.iter().map(|elm| (elm, depth + 1)));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The number of elements in some results (without recursion).
+pub fn count_elements(results: &[graphics::Result]) -> usize {
+    let mut stack: Vec<&graphics::Elm> = vec![];
+    for result in results.iter() {
+        match result {
+            graphics::Result::Ok(graphics::Out::Draw(elm)) => stack.push(elm),
+            graphics::Result::Ok(graphics::Out::Redraw(elms)) => {
+                stack.extend(elms.iter().map(|(_, elm)| elm))
+            }
+            graphics::Result::Err(_) => (),
+        }
+    }
+    let mut count = 0;
+    while let Some(elm) = stack.pop() {
+        count += 1;
+        if let graphics::Elm::Node(node) = elm {
+            stack.extend(node.elms.iter());
+        }
+    }
+    count
+}
+
+/// Drop some results without recursion (unlike their derived `Drop`),
+/// so deep trees cannot overflow the stack.
+pub fn drop_graphics(results: Vec<graphics::Result>) {
+    let mut stack: Vec<graphics::Elm> = vec![];
+    for result in results.into_iter() {
+        match result {
+            graphics::Result::Ok(graphics::Out::Draw(elm)) => stack.push(elm),
+            graphics::Result::Ok(graphics::Out::Redraw(elms)) => {
+                stack.extend(elms.into_iter().map(|(_, elm)| elm))
+            }
+            graphics::Result::Err(_) => (),
+        }
+    }
+    while let Some(elm) = stack.pop() {
+        if let graphics::Elm::Node(mut node) = elm {
+            stack.append(&mut node.elms);
+        }
+    }
+}
+
+/// Graphics buffered for capture, up to a limit (on their elements, in all).
+pub struct GraphicsDump {
+    pub results: Vec<graphics::Result>,
+    elements: usize,
+    max_elements: usize,
+}
+
+impl GraphicsDump {
+    pub fn new(max_elements: usize) -> GraphicsDump {
+        GraphicsDump {
+            results: vec![],
+            elements: 0,
+            max_elements,
+        }
+    }
+
+    /// Buffer some results; once over the limit, drop them (and all later ones).
+    pub fn extend(&mut self, results: Vec<graphics::Result>) {
+        if self.elements > self.max_elements {
+            return drop_graphics(results);
+        }
+        self.elements = self.elements.saturating_add(count_elements(&results));
+        if self.elements > self.max_elements {
+            warn!(
+                "Over {} graphics elements buffered for capture (--max-captured-elements); not buffering more.",
+                self.max_elements
+            );
+            return drop_graphics(results);
+        }
+        self.results.extend(results);
+    }
+}
+
+/// A type in a Candid message's type table.
+enum TableType {
+    Opt(i64),
+    Vec(i64),
+    Record(Vec<i64>),
+    Variant(Vec<i64>),
+    Func,
+    Service,
+}
+
+/// A Candid message, read from its start.
+struct Reader<'a> {
+    blob: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: u64) -> Result<&'a [u8], String> {
+        let end = (self.pos as u64)
+            .checked_add(n)
+            .filter(|end| *end <= self.blob.len() as u64)
+            .ok_or_else(|| "unexpected end of message".to_string())?;
+        let bytes = &self.blob[self.pos..end as usize];
+        self.pos = end as usize;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    /// An unsigned LEB128 number (which must fit in 64 bits).
+    fn leb(&mut self) -> Result<u64, String> {
+        let mut n: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            n |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err("number over 64 bits".to_string())
+    }
+
+    /// A LEB128 number of any size (a `nat` or `int` value), skipped.
+    fn skip_leb(&mut self) -> Result<(), String> {
+        while self.byte()? & 0x80 != 0 {}
+        Ok(())
+    }
+
+    /// A signed LEB128 number (which must fit in 64 bits).
+    fn sleb(&mut self) -> Result<i64, String> {
+        let mut n: i64 = 0;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            n |= ((b & 0x7f) as i64) << shift;
+            if b & 0x80 == 0 {
+                if shift + 7 < 64 && b & 0x40 != 0 {
+                    n |= -1 << (shift + 7);
+                }
+                return Ok(n);
+            }
+        }
+        Err("number over 64 bits".to_string())
+    }
+
+    /// A principal (reference) value.
+    fn principal(&mut self) -> Result<(), String> {
+        if self.byte()? != 1 {
+            return Err("opaque reference".to_string());
+        }
+        let len = self.leb()?;
+        self.bytes(len)?;
+        Ok(())
+    }
+
+    /// A field list of a record or variant type: the type of each field, in order.
+    fn fields(&mut self) -> Result<Vec<i64>, String> {
+        let n = self.leb()?;
+        let mut fields = vec![];
+        for _ in 0..n {
+            let _id = self.leb()?;
+            fields.push(self.sleb()?);
+        }
+        Ok(fields)
+    }
+}
+
+/// Check that a Candid message's values nest at most `max` deep (counting
+/// each optional, vector, record and variant value).
+///
+/// Walks the values without recursion, and without decoding them; the
+/// walk visits at most a few values per byte of a sensible message, and
+/// a message that holds more (e.g., a vector of many empty values) is
+/// rejected too.
+fn candid_nesting(blob: &[u8], max: usize) -> Result<(), String> {
+    let malformed = |e: String| format!("malformed Candid message: {}", e);
+    let mut r = Reader { blob, pos: 0 };
+    if r.bytes(4).map_err(malformed)? != b"DIDL" {
+        return Err(malformed("no magic number".to_string()));
+    }
+    let table_len = r.leb().map_err(malformed)?;
+    let mut table = vec![];
+    for _ in 0..table_len {
+        let ty = match r.sleb().map_err(malformed)? {
+            -18 => TableType::Opt(r.sleb().map_err(malformed)?),
+            -19 => TableType::Vec(r.sleb().map_err(malformed)?),
+            -20 => TableType::Record(r.fields().map_err(malformed)?),
+            -21 => TableType::Variant(r.fields().map_err(malformed)?),
+            -22 => {
+                for _ in 0..2 {
+                    let n = r.leb().map_err(malformed)?;
+                    for _ in 0..n {
+                        r.sleb().map_err(malformed)?;
+                    }
+                }
+                let n = r.leb().map_err(malformed)?;
+                r.bytes(n).map_err(malformed)?;
+                TableType::Func
+            }
+            -23 => {
+                let n = r.leb().map_err(malformed)?;
+                for _ in 0..n {
+                    let len = r.leb().map_err(malformed)?;
+                    r.bytes(len).map_err(malformed)?;
+                    r.sleb().map_err(malformed)?;
+                }
+                TableType::Service
+            }
+            op => return Err(malformed(format!("type table opcode {}", op))),
+        };
+        table.push(ty);
+    }
+    // Pending values: their type, their nesting, and how many of them.
+    let mut stack: Vec<(i64, usize, u64)> = vec![];
+    let args = r.leb().map_err(malformed)?;
+    for _ in 0..args {
+        stack.push((r.sleb().map_err(malformed)?, 1, 1));
+    }
+    stack.reverse();
+    let budget = blob.len().saturating_mul(4).saturating_add(64);
+    let mut visited = 0;
+    while let Some((ty, depth, count)) = stack.pop() {
+        if count == 0 {
+            continue;
+        }
+        if count > 1 {
+            stack.push((ty, depth, count - 1));
+        }
+        visited += 1;
+        if visited > budget {
+            return Err(format!("over {} values in {} bytes", budget, blob.len()));
+        }
+        if depth > max {
+            return Err(format!(
+                "values nested over {} deep (from --max-depth)",
+                max
+            ));
+        }
+        let size = match ty {
+            -1 | -16 => 0, // null, reserved
+            -2 | -5 | -9 => 1,
+            -6 | -10 => 2,
+            -7 | -11 | -13 => 4,
+            -8 | -12 | -14 => 8,
+            -3 | -4 => {
+                r.skip_leb().map_err(malformed)?;
+                0
+            }
+            -15 => r.leb().map_err(malformed)?,
+            -17 => return Err(malformed("value of empty type".to_string())),
+            -24 => {
+                r.principal().map_err(malformed)?;
+                0
+            }
+            ty if ty >= 0 && (ty as u64) < table.len() as u64 => {
+                match &table[ty as usize] {
+                    TableType::Opt(t) => match r.byte().map_err(malformed)? {
+                        0 => (),
+                        1 => stack.push((*t, depth + 1, 1)),
+                        b => return Err(malformed(format!("option flag {}", b))),
+                    },
+                    TableType::Vec(t) => {
+                        let n = r.leb().map_err(malformed)?;
+                        stack.push((*t, depth + 1, n));
+                    }
+                    TableType::Record(fields) => {
+                        stack.extend(fields.iter().rev().map(|t| (*t, depth + 1, 1)))
+                    }
+                    TableType::Variant(fields) => {
+                        let i = r.leb().map_err(malformed)?;
+                        let t = fields
+                            .get(i as usize)
+                            .ok_or_else(|| malformed(format!("variant index {}", i)))?;
+                        stack.push((*t, depth + 1, 1));
+                    }
+                    TableType::Func => {
+                        if r.byte().map_err(malformed)? != 1 {
+                            return Err(malformed("opaque reference".to_string()));
+                        }
+                        r.principal().map_err(malformed)?;
+                        let len = r.leb().map_err(malformed)?;
+                        r.bytes(len).map_err(malformed)?;
+                    }
+                    TableType::Service => r.principal().map_err(malformed)?,
+                }
+                0
+            }
+            ty => return Err(malformed(format!("type {}", ty))),
+        };
+        r.bytes(size).map_err(malformed)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::graphics::{Elm, Fill, Node, Out, Rect};
+    use candid::Encode;
+
+    fn nat(n: u64) -> Nat {
+        Nat::from(n)
+    }
+
+    fn rect(size: u64) -> Elm {
+        Elm::Rect(
+            Rect::new(nat(0), nat(0), nat(size), nat(size)),
+            Fill::Closed((nat(0), nat(0), nat(0))),
+        )
+    }
+
+    fn node(elms: Vec<Elm>) -> Elm {
+        Elm::Node(Box::new(Node {
+            rect: Rect::new(nat(0), nat(0), nat(1), nat(1)),
+            fill: Fill::None,
+            elms,
+        }))
+    }
+
+    /// A rectangle within `depth - 1` nodes (built without recursion).
+    fn deep(depth: usize) -> Elm {
+        (1..depth).fold(rect(1), |elm, _| node(vec![elm]))
+    }
+
+    fn draw(elm: Elm) -> graphics::Result {
+        graphics::Result::Ok(Out::Draw(elm))
+    }
+
+    fn leb(mut n: u64) -> Vec<u8> {
+        let mut bytes = vec![];
+        loop {
+            let b = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                bytes.push(b);
+                return bytes;
+            }
+            bytes.push(b | 0x80);
+        }
+    }
+
+    /// A Candid message: its type table and argument types, then its values.
+    fn message(types: &[u8], values: &[u8]) -> Vec<u8> {
+        [&b"DIDL"[..], types, values].concat()
+    }
+
+    #[test]
+    fn check_bytes() {
+        let limits = Limits {
+            max_response_bytes: 10,
+            ..Limits::default()
+        };
+        assert_eq!(limits.check_bytes(10), Ok(()));
+        assert!(limits.check_bytes(11).unwrap_err().contains("--max-response-bytes"));
+    }
+
+    #[test]
+    fn nesting_of_graphics() {
+        let limits = Limits::default();
+        let blob = Encode!(&vec![draw(deep(limits.max_depth))]).unwrap();
+        assert_eq!(limits.check_nesting(&blob), Ok(()));
+        let blob = Encode!(&vec![draw(deep(limits.max_depth * 2))]).unwrap();
+        assert!(limits.check_nesting(&blob).unwrap_err().contains("nested"));
+    }
+
+    #[test]
+    fn deep_nesting_rejected_before_decoding() {
+        // `type t = opt t`, and a value a million options deep: decoding it
+        // would overflow the stack.
+        let mut values = vec![1u8; 1_000_000];
+        values.push(0);
+        let blob = message(&[1, 0x6e, 0, 1, 0], &values);
+        let err = Limits::default().check_nesting(&blob).unwrap_err();
+        assert!(err.contains("nested"), "{}", err);
+    }
+
+    #[test]
+    fn large_numbers() {
+        // A nat, then an int, each of 100 bytes.
+        let mut number = vec![0xffu8; 99];
+        number.push(0x7f);
+        let blob = message(&[0, 2, 0x7d, 0x7c], &[&number[..], &number[..]].concat());
+        assert_eq!(Limits::default().check_nesting(&blob), Ok(()));
+        let truncated = &blob[..blob.len() - 1];
+        assert!(Limits::default()
+            .check_nesting(truncated)
+            .unwrap_err()
+            .contains("malformed"));
+    }
+
+    #[test]
+    fn visit_budget() {
+        let many = leb(1_000_000_000);
+        // A vector of nulls.
+        let blob = message(&[1, 0x6d, 0x7f, 1, 0], &many);
+        let err = Limits::default().check_nesting(&blob).unwrap_err();
+        assert!(err.contains("values in"), "{}", err);
+        // A vector of empty records.
+        let blob = message(&[2, 0x6c, 0, 0x6d, 0, 1, 1], &many);
+        let err = Limits::default().check_nesting(&blob).unwrap_err();
+        assert!(err.contains("values in"), "{}", err);
+        // (A few are fine.)
+        let blob = message(&[1, 0x6d, 0x7f, 1, 0], &leb(3));
+        assert_eq!(Limits::default().check_nesting(&blob), Ok(()));
+    }
+
+    #[test]
+    fn malformed_messages() {
+        let limits = Limits::default();
+        let malformed = |blob: &[u8]| {
+            let err = limits.check_nesting(blob).unwrap_err();
+            assert!(err.starts_with("malformed Candid message"), "{}", err);
+        };
+        malformed(b"DIDX\x00\x00");
+        malformed(b"DID");
+        // A type table of two types, with only one.
+        malformed(&message(&[2, 0x6d, 0x7f], &[]));
+        // A type table entry that is not a constructed type.
+        malformed(&message(&[1, 0x7f, 1, 0], &[]));
+        // An argument of a type that is not in the table.
+        malformed(&message(&[1, 0x6d, 5, 1, 0], &[1]));
+        // A variant index without a field.
+        malformed(&message(&[1, 0x6b, 1, 0, 0x7f, 1, 0], &[1]));
+        // A vector shorter than its length.
+        malformed(&message(&[1, 0x6d, 0x7b, 1, 0], &[4, 1, 2]));
+    }
+
+    #[test]
+    fn check_graphics() {
+        let limits = Limits {
+            max_depth: 4,
+            max_elements: 10,
+            max_dim: 100,
+            ..Limits::default()
+        };
+        assert_eq!(limits.check_graphics(&[draw(deep(4))]), Ok(()));
+        let err = limits.check_graphics(&[draw(deep(5))]).unwrap_err();
+        assert!(err.contains("--max-depth"), "{}", err);
+
+        let elms = |n| node((1..n).map(|_| rect(1)).collect());
+        assert_eq!(limits.check_graphics(&[draw(elms(10))]), Ok(()));
+        let err = limits.check_graphics(&[draw(elms(11))]).unwrap_err();
+        assert!(err.contains("--max-elements"), "{}", err);
+        // (In all of the results.)
+        let err = limits
+            .check_graphics(&[draw(elms(6)), draw(elms(6))])
+            .unwrap_err();
+        assert!(err.contains("--max-elements"), "{}", err);
+
+        assert_eq!(limits.check_graphics(&[draw(rect(100))]), Ok(()));
+        let err = limits
+            .check_graphics(&[draw(node(vec![rect(101)]))])
+            .unwrap_err();
+        assert!(err.contains("--max-dim"), "{}", err);
+    }
+
+    #[test]
+    fn deep_graphics() {
+        let results = vec![draw(deep(1_000_000))];
+        assert_eq!(count_elements(&results), 1_000_000);
+        assert!(Limits::default().check_graphics(&results).is_err());
+        drop_graphics(results);
+    }
+
+    #[test]
+    fn graphics_dump_cap() {
+        let mut dump = GraphicsDump::new(3);
+        dump.extend(vec![draw(deep(2))]);
+        assert_eq!(dump.results.len(), 1);
+        // Over the limit: dropped, and so are later results.
+        dump.extend(vec![draw(deep(2))]);
+        dump.extend(vec![draw(rect(1))]);
+        assert_eq!(dump.results, vec![draw(deep(2))]);
+        // (Dropped without recursion, however deep.)
+        dump.extend(vec![draw(deep(1_000_000))]);
+        assert_eq!(dump.results.len(), 1);
+    }
+}
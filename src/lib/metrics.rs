@@ -0,0 +1,233 @@
+//! Metrics for service calls and frames: summaries for the HUD, and export.
+//!
+//! Besides the calls themselves, the HUD shows the latency from each view
+//! response (as it arrives) until its frame is presented.
+//!
+//! With `--metrics <file>`, every service call is exported as one record:
+//! as JSON (one object per line) when the file name ends in `.json` or
+//! `.jsonl`, and as CSV otherwise.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::error::{io_err, IcmtResult};
+
+/// Number of recent calls (per method) summarized by the HUD.
+const RECENT_CALLS: usize = 200;
+
+/// Window for counting frames per second.
+const FPS_WINDOW: Duration = Duration::from_secs(2);
+
+/// Kind of service call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CallKind {
+    View,
+    Update,
+}
+
+impl CallKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CallKind::View => "view",
+            CallKind::Update => "update",
+        }
+    }
+}
+
+/// One service call.
+#[derive(Debug, Clone, Serialize)]
+pub struct CallRecord {
+    pub kind: CallKind,
+    /// The method's name (for display; see `--view-method`, `--update-method`).
+    pub method: String,
+    pub start: String,
+    pub elapsed_ms: f64,
+    pub arg_bytes: usize,
+    pub response_bytes: usize,
+    pub ok: bool,
+}
+
+enum Sink {
+    Csv(File),
+    Json(File),
+}
+
+struct State {
+    views: VecDeque<CallRecord>,
+    updates: VecDeque<CallRecord>,
+    frames: VecDeque<Instant>,
+    /// Recent response-to-present latencies, in milliseconds.
+    presents: VecDeque<f64>,
+    sink: Option<Sink>,
+}
+
+/// Metrics collector, shared by the main loop and the service tasks.
+#[derive(Clone)]
+pub struct Metrics {
+    state: Arc<Mutex<State>>,
+}
+
+impl std::fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Metrics")
+    }
+}
+
+/// Summary of recent calls of one kind (view or update).
+#[derive(Debug, Clone, Default)]
+pub struct CallSummary {
+    pub count: usize,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub mean_arg_bytes: f64,
+    pub mean_response_bytes: f64,
+}
+
+/// Summary of recent metrics, for the HUD.
+#[derive(Debug, Clone, Default)]
+pub struct Summary {
+    pub view: CallSummary,
+    pub update: CallSummary,
+    pub fps: f64,
+    pub present_p50_ms: f64,
+    pub present_p99_ms: f64,
+}
+
+impl Metrics {
+    /// Collect metrics; export each call record to the file at `path`, if any.
+    pub fn new(path: Option<&str>) -> IcmtResult<Metrics> {
+        let sink = match path {
+            None => None,
+            Some(path) if path.ends_with(".json") || path.ends_with(".jsonl") => {
+                Some(Sink::Json(File::create(path).map_err(io_err(path))?))
+            }
+            Some(path) => {
+                let mut file = File::create(path).map_err(io_err(path))?;
+                writeln!(
+                    file,
+                    "kind,method,start,elapsed_ms,arg_bytes,response_bytes,ok"
+                )?;
+                Some(Sink::Csv(file))
+            }
+        };
+        Ok(Metrics {
+            state: Arc::new(Mutex::new(State {
+                views: VecDeque::new(),
+                updates: VecDeque::new(),
+                frames: VecDeque::new(),
+                presents: VecDeque::new(),
+                sink,
+            })),
+        })
+    }
+
+    pub fn record_call(&self, rec: CallRecord) -> IcmtResult<()> {
+        let mut state = self.state.lock().unwrap();
+        match &mut state.sink {
+            None => (),
+            Some(Sink::Csv(file)) => {
+                writeln!(
+                    file,
+                    "{},{},{},{:.3},{},{},{}",
+                    rec.kind.name(),
+                    rec.method,
+                    rec.start,
+                    rec.elapsed_ms,
+                    rec.arg_bytes,
+                    rec.response_bytes,
+                    rec.ok
+                )?;
+                file.flush()?;
+            }
+            Some(Sink::Json(file)) => {
+                let line = serde_json::to_string(&rec).map_err(|e| e.to_string())?;
+                writeln!(file, "{}", line)?;
+                file.flush()?;
+            }
+        };
+        let recent = match rec.kind {
+            CallKind::View => &mut state.views,
+            CallKind::Update => &mut state.updates,
+        };
+        recent.push_back(rec);
+        if recent.len() > RECENT_CALLS {
+            recent.pop_front();
+        }
+        Ok(())
+    }
+
+    /// Note that a frame was presented (now).
+    pub fn record_frame(&self) {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        state.frames.push_back(now);
+        while let Some(t) = state.frames.front() {
+            if now.duration_since(*t) > FPS_WINDOW {
+                state.frames.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Note the latency from a view response until its frame was presented.
+    pub fn record_present(&self, latency: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.presents.push_back(latency.as_secs_f64() * 1000.0);
+        if state.presents.len() > RECENT_CALLS {
+            state.presents.pop_front();
+        }
+    }
+
+    pub fn summary(&self) -> Summary {
+        let state = self.state.lock().unwrap();
+        let fps = match state.frames.front() {
+            Some(t) if state.frames.len() > 1 => {
+                let secs = t.elapsed().as_secs_f64().max(FPS_WINDOW.as_secs_f64() / 10.0);
+                (state.frames.len() - 1) as f64 / secs
+            }
+            _ => 0.0,
+        };
+        let mut presents: Vec<f64> = state.presents.iter().cloned().collect();
+        presents.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        Summary {
+            view: summarize(&state.views),
+            update: summarize(&state.updates),
+            fps,
+            present_p50_ms: percentile(&presents, 50.0),
+            present_p99_ms: percentile(&presents, 99.0),
+        }
+    }
+}
+
+fn summarize(recs: &VecDeque<CallRecord>) -> CallSummary {
+    let ok: Vec<&CallRecord> = recs.iter().filter(|r| r.ok).collect();
+    if ok.len() == 0 {
+        return CallSummary::default();
+    }
+    let mut ms: Vec<f64> = ok.iter().map(|r| r.elapsed_ms).collect();
+    ms.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let n = ok.len() as f64;
+    CallSummary {
+        count: ok.len(),
+        p50_ms: percentile(&ms, 50.0),
+        p90_ms: percentile(&ms, 90.0),
+        p99_ms: percentile(&ms, 99.0),
+        mean_arg_bytes: ok.iter().map(|r| r.arg_bytes as f64).sum::<f64>() / n,
+        mean_response_bytes: ok.iter().map(|r| r.response_bytes as f64).sum::<f64>() / n,
+    }
+}
+
+/// Nearest-rank percentile of sorted values (zero, when there are none).
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    if sorted.len() == 0 {
+        return 0.0;
+    }
+    sorted[(((p / 100.0) * sorted.len() as f64).ceil() as usize).max(1) - 1]
+}
@@ -9,10 +9,26 @@ extern crate serde;
 extern crate serde_bytes;
 //extern crate candid_derive;
 
+pub mod bridge;
 pub mod cli;
 pub mod color;
+pub mod config;
+pub mod dfx;
+pub mod display;
 pub mod draw;
 pub mod error;
+pub mod frontend;
+pub mod handshake;
+pub mod hud;
+pub mod iface;
 pub mod keyboard;
+pub mod limits;
+pub mod metrics;
+pub mod script;
+pub mod sdl;
+pub mod service;
+pub mod session;
+pub mod trace;
+pub mod tty;
 pub mod types;
 pub mod write;
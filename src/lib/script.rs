@@ -0,0 +1,351 @@
+//! Input scripts: human-readable input, for automation (`ic-mt script`).
+//!
+//! A script has one command per line (and `#` comments):
+//!
+//! | Command | Events |
+//! |---------|--------|
+//! | `type "Hello, world"` | a key press per character (`\n` is `Enter`), and shift's before shifted ones |
+//! | `key Enter`, `key ctrl+c`, `key shift+1` | one key press, with modifiers (`alt`, `ctrl`, `meta`, `shift`) |
+//! | `paste "some text"` | a clipboard update |
+//! | `click 10,20` | a mouse press, at that position |
+//! | `resize 640x480` | a window-size change |
+//! | `wait 500ms`, `wait 2s` | none; a pause |
+//! | `quit` | the end (also implied, after the last line) |
+//!
+//! Each event is what the window would give for the same input (on a US
+//! keyboard, so `shift+1` is `!`), so a script drives a canister just as a
+//! local user does.
+
+use candid::Nat;
+use std::collections::VecDeque;
+use std::fs;
+use std::time::{Duration, Instant};
+
+use crate::cli::Size;
+use crate::error::{io_err, IcmtError, IcmtResult};
+use crate::frontend::{Headless, Input, InputSource, Waker};
+use crate::keyboard::{needs_shift, shifted};
+use crate::types::{event, graphics};
+
+/// Key names (besides single characters).
+const KEY_NAMES: &[&str] = &[
+    "Enter",
+    "Tab",
+    "Backspace",
+    "ArrowLeft",
+    "ArrowRight",
+    "ArrowUp",
+    "ArrowDown",
+    "Shift",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Action {
+    Event(event::Event),
+    Wait(Duration),
+}
+
+/// Input from a script.
+pub struct ScriptInput {
+    actions: VecDeque<Action>,
+    wait_until: Option<Instant>,
+    /// Waits (and wakes) between actions, and after the last one.
+    idle: Headless,
+}
+
+impl ScriptInput {
+    /// Load (and check) a script file.
+    pub fn load(path: &str) -> IcmtResult<ScriptInput> {
+        let text = fs::read_to_string(path).map_err(io_err(path))?;
+        let actions = parse(&text)
+            .map_err(|(line, e)| IcmtError::Usage(format!("{}:{}: {}", path, line, e)))?;
+        Ok(ScriptInput {
+            actions: actions.into(),
+            wait_until: None,
+            idle: Headless::new(graphics::Dim {
+                width: Nat::from(0),
+                height: Nat::from(0),
+            }),
+        })
+    }
+}
+
+impl InputSource for ScriptInput {
+    fn next_input(&mut self, timeout: Duration) -> IcmtResult<Option<Input>> {
+        if let Some(until) = self.wait_until {
+            let now = Instant::now();
+            if now < until {
+                return self.idle.next_input((until - now).min(timeout));
+            }
+            self.wait_until = None;
+        }
+        match self.actions.pop_front() {
+            Some(Action::Event(event)) => Ok(Some(Input::Event(event))),
+            Some(Action::Wait(duration)) => {
+                self.wait_until = Some(Instant::now() + duration);
+                Ok(None)
+            }
+            None => self.idle.next_input(timeout),
+        }
+    }
+
+    fn waker(&self) -> Waker {
+        self.idle.waker()
+    }
+}
+
+/// Parse a script; on failure, the line number (from 1) and the reason.
+fn parse(text: &str) -> Result<Vec<Action>, (usize, String)> {
+    let mut actions = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (command, arg) = match line.find(char::is_whitespace) {
+            Some(n) => (&line[..n], line[n..].trim()),
+            None => (line, ""),
+        };
+        parse_command(command, arg, &mut actions).map_err(|e| (i + 1, e))?;
+    }
+    if actions.last() != Some(&Action::Event(event::Event::Quit)) {
+        actions.push(Action::Event(event::Event::Quit));
+    }
+    Ok(actions)
+}
+
+fn parse_command(command: &str, arg: &str, actions: &mut Vec<Action>) -> Result<(), String> {
+    match command {
+        "type" => {
+            // (shift goes down before a run of shifted characters, as in the window.)
+            let mut shift_down = false;
+            for c in parse_string(arg)?.chars() {
+                let key = match c {
+                    '\n' => key_info("Enter", false),
+                    '\t' => key_info("Tab", false),
+                    c => key_info(&c.to_string(), needs_shift(c)),
+                };
+                if key.shift && !shift_down {
+                    actions.push(Action::Event(shift_key()));
+                }
+                shift_down = key.shift;
+                actions.push(Action::Event(event::Event::KeyDown(vec![key])));
+            }
+        }
+        "key" => {
+            let event = parse_key(arg)?;
+            if let event::Event::KeyDown(keys) = &event {
+                if keys[0].shift && keys[0].key != "Shift" {
+                    actions.push(Action::Event(shift_key()));
+                }
+            }
+            actions.push(Action::Event(event));
+        }
+        "paste" => actions.push(Action::Event(event::Event::ClipBoard(parse_string(arg)?))),
+        "click" => {
+            let err = || format!("expected a position like 10,20, not {:?}", arg);
+            let mut parts = arg.splitn(2, ',');
+            let mut coord = || -> Result<u64, String> {
+                let part = parts.next().ok_or_else(err)?;
+                part.trim().parse().map_err(|_| err())
+            };
+            let (x, y) = (coord()?, coord()?);
+            actions.push(Action::Event(event::Event::MouseDown(graphics::Pos {
+                x: Nat::from(x),
+                y: Nat::from(y),
+            })));
+        }
+        "resize" => {
+            let size: Size = arg.parse()?;
+            actions.push(Action::Event(event::Event::WindowSize(size.dim())));
+        }
+        "wait" => actions.push(Action::Wait(parse_duration(arg)?)),
+        "quit" if arg.is_empty() => actions.push(Action::Event(event::Event::Quit)),
+        "quit" => return Err(format!("quit takes no argument, not {:?}", arg)),
+        _ => return Err(format!("unknown command {:?}", command)),
+    };
+    Ok(())
+}
+
+/// The shift key's own press (the window reports it, as held).
+fn shift_key() -> event::Event {
+    event::Event::KeyDown(vec![key_info("Shift", true)])
+}
+
+fn key_info(key: &str, shift: bool) -> event::KeyEventInfo {
+    event::KeyEventInfo {
+        key: key.to_string(),
+        alt: false,
+        ctrl: false,
+        meta: false,
+        shift,
+    }
+}
+
+/// A key press, like `a`, `Enter`, `ctrl+c` or `shift+Tab`; `Escape` quits (as in the window).
+fn parse_key(arg: &str) -> Result<event::Event, String> {
+    let mut parts: Vec<&str> = arg.split('+').collect();
+    // (a lone "+" is the key itself, not a separator.)
+    let name = if arg.ends_with("++") || arg == "+" {
+        parts.truncate(parts.len().saturating_sub(2));
+        "+"
+    } else {
+        parts.pop().unwrap_or("")
+    };
+    let mut info = key_info(name, false);
+    for modifier in parts {
+        match modifier {
+            "alt" => info.alt = true,
+            "ctrl" => info.ctrl = true,
+            "meta" => info.meta = true,
+            "shift" => info.shift = true,
+            m => return Err(format!("unknown key modifier {:?}", m)),
+        }
+    }
+    match name {
+        "Escape" => return Ok(event::Event::Quit),
+        "Space" => info.key = " ".to_string(),
+        "Shift" => info.shift = true,
+        name if KEY_NAMES.contains(&name) => (),
+        name if name.chars().count() == 1 => {
+            let c = name.chars().next().unwrap();
+            if info.shift {
+                info.key = shifted(c);
+            } else if needs_shift(c) {
+                info.shift = true;
+            }
+        }
+        name => return Err(format!("unknown key {:?}", name)),
+    }
+    Ok(event::Event::KeyDown(vec![info]))
+}
+
+/// A string literal, in double quotes, with `\"`, `\\`, `\n` and `\t` escapes.
+fn parse_string(arg: &str) -> Result<String, String> {
+    let err = || format!("expected a string in double quotes, not {:?}", arg);
+    if arg.len() < 2 || !arg.starts_with('"') || !arg.ends_with('"') {
+        return Err(err());
+    }
+    let mut s = String::new();
+    let mut chars = arg[1..arg.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => s.push('\n'),
+                Some('t') => s.push('\t'),
+                Some(c @ '"') | Some(c @ '\\') => s.push(c),
+                _ => return Err(err()),
+            },
+            '"' => return Err(err()),
+            c => s.push(c),
+        }
+    }
+    Ok(s)
+}
+
+/// A duration, like `500ms` or `2s`.
+fn parse_duration(arg: &str) -> Result<Duration, String> {
+    let err = || format!("expected a duration like 500ms or 2s, not {:?}", arg);
+    if let Some(ms) = arg.strip_suffix("ms") {
+        Ok(Duration::from_millis(ms.trim().parse().map_err(|_| err())?))
+    } else if let Some(s) = arg.strip_suffix('s') {
+        let secs: f64 = s.trim().parse().map_err(|_| err())?;
+        if !secs.is_finite() || secs < 0.0 {
+            return Err(err());
+        }
+        Ok(Duration::from_secs_f64(secs))
+    } else {
+        Err(err())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(key: &str, shift: bool) -> event::Event {
+        event::Event::KeyDown(vec![key_info(key, shift)])
+    }
+
+    fn keys(actions: Vec<Action>) -> Vec<event::Event> {
+        actions
+            .into_iter()
+            .map(|action| match action {
+                Action::Event(event) => event,
+                Action::Wait(_) => panic!("unexpected wait"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn keys_with_modifiers() {
+        let mut ctrl_plus = key_info("+", true);
+        ctrl_plus.ctrl = true;
+        assert_eq!(
+            parse_key("ctrl++"),
+            Ok(event::Event::KeyDown(vec![ctrl_plus]))
+        );
+        assert_eq!(parse_key("+"), Ok(key("+", true)));
+        assert_eq!(parse_key("shift+1"), Ok(key("!", true)));
+        assert_eq!(parse_key("shift+["), Ok(key("{", true)));
+        assert_eq!(parse_key("shift+a"), Ok(key("A", true)));
+        assert_eq!(parse_key("shift+Tab"), Ok(key("Tab", true)));
+        assert_eq!(parse_key("?"), Ok(key("?", true)));
+        assert_eq!(parse_key("Space"), Ok(key(" ", false)));
+        assert_eq!(parse_key("Escape"), Ok(event::Event::Quit));
+        assert!(parse_key("hyper+a").unwrap_err().contains("modifier"));
+        assert!(parse_key("F13").is_err());
+    }
+
+    #[test]
+    fn shift_goes_down_first() {
+        let actions = parse("type \"aB!\\n\"\nkey shift+1").unwrap();
+        assert_eq!(
+            keys(actions),
+            vec![
+                key("a", false),
+                key("Shift", true),
+                key("B", true),
+                key("!", true),
+                key("Enter", false),
+                key("Shift", true),
+                key("!", true),
+                event::Event::Quit,
+            ]
+        );
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(
+            parse_string(r#""a\"b\\c\nd\te""#),
+            Ok("a\"b\\c\nd\te".to_string())
+        );
+        assert_eq!(parse_string(r#""""#), Ok(String::new()));
+        assert!(parse_string(r#""\x""#).is_err());
+        assert!(parse_string(r#""a"b""#).is_err());
+        assert!(parse_string(r#""\""#).is_err());
+        assert!(parse_string("abc").is_err());
+        assert!(parse_string("\"").is_err());
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("2s"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert!(parse_duration("-1s").is_err());
+        assert!(parse_duration("1.5ms").is_err());
+        assert!(parse_duration("500").is_err());
+        assert!(parse_duration("ms").is_err());
+    }
+
+    #[test]
+    fn errors_give_line_numbers() {
+        let text = "# comment\n\nkey Enter\n  bogus 1\n";
+        let (line, e) = parse(text).unwrap_err();
+        assert_eq!(line, 4);
+        assert!(e.contains("bogus"));
+        assert_eq!(parse("wait 1s\nquit now").unwrap_err().0, 2);
+    }
+}
@@ -0,0 +1,287 @@
+//! SDL front end: a window, for input (keyboard, mouse and clipboard) and graphics.
+
+use candid::Nat;
+use log::{trace, warn};
+use sdl2::event::Event as SysEvent; // not to be confused with our own definition
+use sdl2::event::{EventSender, WindowEvent};
+use sdl2::keyboard::Keycode;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::cli::{CliOpt, Size};
+use crate::display::DisplayList;
+use crate::draw::{draw_list, draw_status};
+use crate::error::IcmtResult;
+use crate::frontend::{Input, InputSource, Overlay, Renderer, Waker};
+use crate::handshake::Capabilities;
+use crate::hud::draw_hud;
+use crate::keyboard;
+use crate::types::{event, graphics, nat_ceil, ConnectionStatus};
+
+pub const WINDOW_TITLE: &str = "IC Mini Terminal";
+
+/// SDL user event that wakes the main loop (see `InputSource::waker`).
+struct Wake;
+
+/// Window output.
+pub struct SdlRenderer {
+    canvas: Canvas<Window>,
+    no_window: bool,
+    dim: graphics::Dim,
+    /// The last frame, compiled.
+    last_frame: Option<DisplayList>,
+    status: ConnectionStatus,
+    caps: Capabilities,
+}
+
+/// Window input.
+pub struct SdlInput {
+    video_subsystem: sdl2::VideoSubsystem,
+    event_pump: sdl2::EventPump,
+    event_sender: Arc<EventSender>,
+    /// The canister's dimension (fixed, with a logical size).
+    dim: graphics::Dim,
+    logical_size: Option<Size>,
+}
+
+/// Open the window, as configured on the command line.
+pub fn open(cli_opt: &CliOpt) -> IcmtResult<(SdlRenderer, SdlInput)> {
+    let sdl = sdl2::init()?;
+
+    // to do --- if headless, do not do these steps; window_canvas is None
+    let video_subsystem = sdl.video()?;
+    let window = {
+        let size = &cli_opt.window_size;
+        let mut builder = video_subsystem.window(WINDOW_TITLE, size.width, size.height);
+        builder.position_centered().resizable();
+        /*.input_grabbed() // to do -- CI flag*/
+        if cli_opt.fullscreen {
+            builder.fullscreen_desktop();
+        }
+        builder.build().map_err(|e| e.to_string())?
+    };
+
+    // With a logical size, the canister always sees that dimension;
+    // otherwise, it sees the window's (current) dimension.
+    let logical_size = cli_opt.logical_size;
+    let dim = match logical_size {
+        Some(size) => size.dim(),
+        None => {
+            let (width, height) = window.size();
+            graphics::Dim {
+                width: Nat::from(width),
+                height: Nat::from(height),
+            }
+        }
+    };
+
+    let mut canvas = window
+        .into_canvas()
+        .target_texture()
+        .present_vsync()
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    if let Some(size) = logical_size {
+        // SDL scales drawing by the largest integer factor that fits the
+        // window, letterboxes the rest, and maps mouse positions back.
+        canvas
+            .set_logical_size(size.width, size.height)
+            .map_err(|e| e.to_string())?;
+        canvas.set_integer_scale(true)?;
+    }
+
+    let event_pump = {
+        use sdl2::event::EventType;
+        let mut p = sdl.event_pump()?;
+        p.disable_event(EventType::FingerUp);
+        p.disable_event(EventType::FingerDown);
+        p.disable_event(EventType::FingerMotion);
+        p.disable_event(EventType::MouseMotion);
+        p
+    };
+    let event_sender = {
+        let events = sdl.event()?;
+        events.register_custom_event::<Wake>()?;
+        Arc::new(events.event_sender())
+    };
+
+    let renderer = SdlRenderer {
+        canvas,
+        no_window: cli_opt.no_window,
+        dim: dim.clone(),
+        last_frame: None,
+        status: ConnectionStatus::Connected,
+        caps: Capabilities::all(),
+    };
+    let input = SdlInput {
+        video_subsystem,
+        event_pump,
+        event_sender,
+        dim,
+        logical_size,
+    };
+    Ok((renderer, input))
+}
+
+impl SdlRenderer {
+    fn set_status(&mut self, status: &ConnectionStatus) -> IcmtResult<()> {
+        if *status == self.status {
+            return Ok(());
+        }
+        self.status = status.clone();
+        let title = match status {
+            ConnectionStatus::Connected => WINDOW_TITLE.to_string(),
+            status => format!("{} ({})", WINDOW_TITLE, status),
+        };
+        self.canvas
+            .window_mut()
+            .set_title(&title)
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Draw the last frame (if any) and the overlay, and present them.
+    fn present(&mut self, overlay: &Overlay) -> IcmtResult<()> {
+        if self.no_window {
+            return Ok(());
+        }
+        if let Some(list) = &self.last_frame {
+            draw_list(&mut self.canvas, list)?;
+        }
+        draw_status(&mut self.canvas, &self.dim, &overlay.status)?;
+        if let Some((summary, queue_depth)) = &overlay.hud {
+            draw_hud(&mut self.canvas, summary, *queue_depth)?;
+        }
+        self.canvas.present();
+        Ok(())
+    }
+}
+
+impl Renderer for SdlRenderer {
+    fn dim(&self) -> graphics::Dim {
+        self.dim.clone()
+    }
+
+    fn render(
+        &mut self,
+        dim: &graphics::Dim,
+        frame: &graphics::Result,
+        overlay: &Overlay,
+    ) -> IcmtResult<()> {
+        self.dim = dim.clone();
+        self.last_frame = Some(DisplayList::compile_for(dim, frame, &self.caps));
+        self.set_status(&overlay.status)?;
+        self.present(overlay)
+    }
+
+    fn redraw(&mut self, overlay: &Overlay) -> IcmtResult<()> {
+        self.set_status(&overlay.status)?;
+        self.present(overlay)
+    }
+
+    fn negotiated(&mut self, caps: &Capabilities) {
+        self.caps = caps.clone();
+    }
+}
+
+impl InputSource for SdlInput {
+    fn next_input(&mut self, timeout: Duration) -> IcmtResult<Option<Input>> {
+        let system_event = match self
+            .event_pump
+            .wait_event_timeout(timeout.as_millis() as u32)
+        {
+            Some(system_event) => system_event,
+            None => return Ok(None),
+        };
+        trace!("SDL event_pump.wait_event() => {:?}", &system_event);
+        if system_event.is_user_event() {
+            return Ok(None); // woken
+        }
+        if let SysEvent::KeyDown {
+            keycode: Some(Keycode::F1),
+            ..
+        } = system_event
+        {
+            return Ok(Some(Input::ToggleHud));
+        }
+        match translate_system_event(&self.video_subsystem, &self.dim, &system_event) {
+            // the logical dimension is fixed; rescale the last frame.
+            Some(event::Event::WindowSize(_)) if self.logical_size.is_some() => {
+                Ok(Some(Input::Redraw))
+            }
+            Some(event::Event::WindowSize(dim)) => {
+                self.dim = dim.clone();
+                Ok(Some(Input::Event(event::Event::WindowSize(dim))))
+            }
+            Some(event) => Ok(Some(Input::Event(event))),
+            None => Ok(None),
+        }
+    }
+
+    fn waker(&self) -> Waker {
+        let event_sender = self.event_sender.clone();
+        Arc::new(move || {
+            if let Err(e) = event_sender.push_custom_event(Wake) {
+                warn!("Cannot wake main loop: {}", e);
+            }
+        })
+    }
+}
+
+pub fn translate_system_event(
+    video_subsystem: &sdl2::VideoSubsystem,
+    window_dim: &graphics::Dim,
+    event: &SysEvent,
+) -> Option<event::Event> {
+    match event {
+        SysEvent::MouseButtonDown { x, y, .. } => {
+            // Positions are logical (when scaled); ignore clicks on the letterbox.
+            if *x < 0
+                || *y < 0
+                || *x as u32 >= nat_ceil(&window_dim.width)
+                || *y as u32 >= nat_ceil(&window_dim.height)
+            {
+                return None;
+            }
+            let pos = graphics::Pos {
+                x: Nat::from(*x as u64),
+                y: Nat::from(*y as u64),
+            };
+            Some(event::Event::MouseDown(pos))
+        }
+        SysEvent::ClipboardUpdate { .. } => {
+            let text = match video_subsystem.clipboard().clipboard_text() {
+                Ok(text) => text,
+                Err(text) => format!("error: {}", text),
+            };
+            Some(event::Event::ClipBoard(text))
+        }
+        SysEvent::Window {
+            win_event: WindowEvent::SizeChanged(w, h),
+            ..
+        } => {
+            let dim = graphics::Dim {
+                width: Nat::from(*w as u64),
+                height: Nat::from(*h as u64),
+            };
+            Some(event::Event::WindowSize(dim))
+        }
+        SysEvent::Quit { .. }
+        | SysEvent::KeyDown {
+            keycode: Some(Keycode::Escape),
+            ..
+        } => Some(event::Event::Quit),
+        SysEvent::KeyDown {
+            keycode: Some(ref kc),
+            keymod,
+            ..
+        } => match keyboard::translate_event(kc, keymod) {
+            Some(ev) => Some(event::Event::KeyDown(vec![ev])),
+            None => None,
+        },
+        _ => None,
+    }
+}
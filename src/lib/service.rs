@@ -0,0 +1,420 @@
+//! Service calls to the canister, and the view and update tasks that make them.
+//!
+//! The tasks share one agent, and report their connection status (and
+//! their failure, if any) to the session's main loop.  Each task stops when
+//! its request channel closes (the update task first finishes the requests
+//! already sent), or when it is cancelled.
+
+use chrono::prelude::*;
+use ic_agent::Agent;
+use ic_types::Principal;
+use log::{debug, error, info, trace, warn};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use crate::error::{IcmtError, IcmtResult};
+use crate::frontend::Waker;
+use crate::limits::drop_graphics;
+use crate::metrics::{CallKind, CallRecord};
+use crate::session::{ConnectCfg, ConnectCtx, Identity};
+use crate::trace::{ArgShape, TraceEntry};
+use crate::types::{event, graphics, ConnectionStatus, ServiceCall};
+
+const RETRY_PAUSE: Duration = Duration::from_millis(100);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+const RETRY_BACKOFF_MIN: Duration = Duration::from_millis(250);
+const RETRY_BACKOFF_MAX: Duration = Duration::from_secs(8);
+const RECONNECT_AFTER: usize = 3;
+
+pub async fn create_agent(cfg: &ConnectCfg) -> IcmtResult<Agent> {
+    //use ring::signature::Ed25519KeyPair;
+    use ring::rand::SystemRandom;
+    info!("creating agent.");
+
+    let ident = match &cfg.identity {
+        Identity::PemFile(path) => ic_agent::identity::BasicIdentity::from_pem_file(path)
+            .map_err(|e| IcmtError::Usage(format!("identity {}: {}", path, e)))?,
+        Identity::Random => {
+            let rng = SystemRandom::new();
+            let pkcs8_bytes = ring::signature::Ed25519KeyPair::generate_pkcs8(&rng)?;
+            let key_pair = ring::signature::Ed25519KeyPair::from_pkcs8(pkcs8_bytes.as_ref())?;
+            ic_agent::identity::BasicIdentity::from_key_pair(key_pair)
+        }
+    };
+    let agent = Agent::builder()
+        .with_url(&cfg.replica_url)
+        .with_identity(ident)
+        .build()?;
+    info!("built agent.");
+    if cfg.trace_player.is_some() {
+        info!("offline trace: not fetching root key.");
+        return Ok(agent);
+    }
+    if true {
+        // to do -- CLI switch.
+        agent.fetch_root_key().await?;
+    }
+    info!("got root key.");
+    Ok(agent)
+}
+
+/// The agent shared by the view and update tasks.
+///
+/// Either task may replace it, when reconnecting.
+#[derive(Clone)]
+pub struct SharedAgent(Arc<Mutex<Agent>>);
+
+impl SharedAgent {
+    pub fn new(agent: Agent) -> SharedAgent {
+        SharedAgent(Arc::new(Mutex::new(agent)))
+    }
+
+    pub fn get(&self) -> Agent {
+        self.0.lock().unwrap().clone()
+    }
+
+    pub fn set(&self, agent: Agent) {
+        *self.0.lock().unwrap() = agent;
+    }
+}
+
+/// Connection status, for the session's main loop.
+///
+/// Each report wakes the main loop, as does each response (see `wake`).
+#[derive(Clone)]
+pub struct StatusOut {
+    status: mpsc::UnboundedSender<ConnectionStatus>,
+    wake: Waker,
+}
+
+impl StatusOut {
+    pub fn new(status: mpsc::UnboundedSender<ConnectionStatus>, wake: Waker) -> StatusOut {
+        StatusOut { status, wake }
+    }
+
+    pub fn send(&self, status: ConnectionStatus) -> IcmtResult<()> {
+        self.status.send(status)?;
+        self.wake();
+        Ok(())
+    }
+
+    /// Wake the main loop, e.g., to take a response.
+    pub fn wake(&self) {
+        (self.wake)()
+    }
+}
+
+/// Create an agent, retrying (with backoff) while the replica is unreachable.
+pub async fn connect_with_retry(
+    cfg: &ConnectCfg,
+    status_out: &StatusOut,
+) -> IcmtResult<Agent> {
+    let mut failures = 0;
+    let mut backoff = RETRY_BACKOFF_MIN;
+    loop {
+        match create_agent(cfg).await {
+            Ok(agent) => return Ok(agent),
+            Err(e) if e.is_transient() => {
+                failures += 1;
+                if cfg.opts.max_retries > 0 && failures > cfg.opts.max_retries {
+                    return Err(e);
+                }
+                warn!("Cannot create agent (attempt {}): {:?}", failures, e);
+                status_out.send(ConnectionStatus::Reconnecting)?;
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(RETRY_BACKOFF_MAX);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Perform a service call, retrying (with backoff) on transient failures.
+///
+/// The same call (hence, the same event batch) is resent until it succeeds,
+/// so no events are lost; after every `RECONNECT_AFTER` consecutive failures,
+/// the (shared) agent is re-created.
+///
+/// An update that failed in transit (or timed out) may have reached the
+/// canister anyway, so a retry can deliver its events twice; canisters
+/// tell them apart by their session id and nonce (see `Nonces`).
+pub async fn service_call_retry(
+    ctx: &mut ConnectCtx,
+    shared_agent: &SharedAgent,
+    call: ServiceCall,
+    status_out: &StatusOut,
+) -> IcmtResult<Vec<graphics::Result>> {
+    let mut failures = 0;
+    let mut backoff = RETRY_BACKOFF_MIN;
+    loop {
+        ctx.agent = shared_agent.get();
+        match service_call(ctx, call.clone()).await {
+            Ok(r) => {
+                if failures > 0 {
+                    info!("Service call succeeded after {} failures.", failures);
+                    status_out.send(ConnectionStatus::Connected)?;
+                }
+                return Ok(r);
+            }
+            Err(e) if e.is_transient() => {
+                failures += 1;
+                if ctx.cfg.opts.max_retries > 0 && failures > ctx.cfg.opts.max_retries {
+                    error!("Giving up after {} failed attempts.", failures);
+                    return Err(e);
+                }
+                warn!(
+                    "Service call failed (attempt {}); retrying in {:?}.",
+                    failures, backoff
+                );
+                if failures % RECONNECT_AFTER == 0 {
+                    status_out.send(ConnectionStatus::Reconnecting)?;
+                    shared_agent.set(connect_with_retry(&ctx.cfg, status_out).await?);
+                } else {
+                    status_out.send(ConnectionStatus::Retrying(failures))?;
+                }
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(RETRY_BACKOFF_MAX);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Run a view or update task; report its failure (if any) as a connection status.
+pub async fn report_task_failure(
+    task_name: &'static str,
+    status_out: StatusOut,
+    task: impl std::future::Future<Output = IcmtResult<()>>,
+) {
+    if let Err(e) = task.await {
+        error!("{} task failed: {}", task_name, e);
+        let _ = status_out.send(ConnectionStatus::Failed(e.to_string()));
+    }
+    // (its channels have closed.)
+    status_out.wake();
+}
+
+/// View task: answers each view request with the canister's graphics
+/// (and when they arrived, to measure their latency until presented).
+///
+/// Stops (abandoning any call in progress) when cancelled.
+pub async fn do_view_task(
+    mut ctx: ConnectCtx,
+    shared_agent: SharedAgent,
+    mut remote_in: mpsc::Receiver<(graphics::Dim, Vec<event::EventInfo>)>,
+    remote_out: mpsc::Sender<(graphics::Result, Instant)>,
+    status_out: StatusOut,
+    cancel: CancellationToken,
+) -> IcmtResult<()> {
+    loop {
+        let (window_dim, events) = tokio::select! {
+            _ = cancel.cancelled() => return Ok(()),
+            req = remote_in.recv() => match req {
+                Some(req) => req,
+                None => return Ok(()), /* main loop has stopped */
+            },
+        };
+        let call = ServiceCall::View(window_dim, events);
+        let mut rr = tokio::select! {
+            _ = cancel.cancelled() => return Ok(()),
+            rr = service_call_retry(&mut ctx, &shared_agent, call, &status_out) => rr?,
+        };
+        assert_eq!(rr.len(), 1);
+        if remote_out.send((rr.remove(0), Instant::now())).await.is_err() {
+            return Ok(());
+        }
+        status_out.wake();
+    }
+}
+
+/// Update task: sends each batch of events, and answers with the requested graphics.
+///
+/// When its request channel closes, it finishes the requests already sent;
+/// when cancelled, it stops at once.
+pub async fn do_update_task(
+    mut ctx: ConnectCtx,
+    shared_agent: SharedAgent,
+    mut remote_in: mpsc::Receiver<(Vec<event::EventInfo>, graphics::Request)>,
+    remote_out: mpsc::Sender<Vec<graphics::Result>>,
+    status_out: StatusOut,
+    cancel: CancellationToken,
+) -> IcmtResult<()> {
+    loop {
+        let (events, req) = tokio::select! {
+            _ = cancel.cancelled() => return Ok(()),
+            sc = remote_in.recv() => match sc {
+                Some(sc) => sc,
+                None => return Ok(()), /* main loop has stopped, and all requests are done */
+            },
+        };
+        let call = ServiceCall::Update(events, req);
+        let r = tokio::select! {
+            _ = cancel.cancelled() => return Ok(()),
+            r = service_call_retry(&mut ctx, &shared_agent, call, &status_out) => r?,
+        };
+        if remote_out.send(r).await.is_err() {
+            return Ok(());
+        }
+        status_out.wake();
+    }
+}
+
+pub async fn service_call(
+    ctx: &ConnectCtx,
+    call: ServiceCall,
+) -> IcmtResult<Vec<graphics::Result>> {
+    let prefix = match &call {
+        ServiceCall::View { .. } => "Service (view):",
+        ServiceCall::Update { .. } => "Service (update):",
+    };
+    debug!(
+        "{}: to canister_id {:?} at replica_url {:?}",
+        prefix, ctx.cfg.canister_id, ctx.cfg.replica_url
+    );
+    let delay = garcon::Delay::builder()
+        .throttle(RETRY_PAUSE)
+        .timeout(REQUEST_TIMEOUT)
+        .build();
+    let timestamp = std::time::SystemTime::now();
+    let arg_bytes = call.encode_arg(&ctx.cfg.opts.extra_args)?;
+    info!(
+        "{}: Encoded argument via Candid; Arg size {:?} bytes",
+        prefix,
+        arg_bytes.len()
+    );
+    info!("{}: Awaiting response from service...", prefix);
+    let method = match &call {
+        ServiceCall::View { .. } => ctx.cfg.opts.view_method.as_str(),
+        ServiceCall::Update { .. } => ctx.cfg.opts.update_method.as_str(),
+    };
+    // do an update or query call, based on the ServiceCall case:
+    let blob_res = match &call {
+        _ if ctx.cfg.trace_player.is_some() => {
+            let player = ctx.cfg.trace_player.as_ref().unwrap();
+            let extra_args = ctx.cfg.opts.extra_args.args.len();
+            let shape = match &call {
+                ServiceCall::View { .. } => ArgShape::View(extra_args),
+                ServiceCall::Update { .. } => ArgShape::Update(extra_args),
+            };
+            player.respond(method, &arg_bytes, shape)
+        }
+        ServiceCall::View(_window_dim, _keys) => ctx
+            .agent
+            .query(&ctx.canister_id, method)
+            .with_arg(arg_bytes.clone())
+            .call()
+            .await
+            .map_err(IcmtError::from),
+        ServiceCall::Update(_keys, _gfx_req) => ctx
+            .agent
+            .update(&ctx.canister_id, method)
+            .with_arg(arg_bytes.clone())
+            .call_and_wait(delay)
+            .await
+            .map_err(IcmtError::from),
+    };
+    let elapsed = timestamp.elapsed().unwrap();
+    ctx.cfg.metrics.record_call(CallRecord {
+        kind: match &call {
+            ServiceCall::View { .. } => CallKind::View,
+            ServiceCall::Update { .. } => CallKind::Update,
+        },
+        method: method.to_string(),
+        start: DateTime::<Local>::from(timestamp).to_rfc3339(),
+        elapsed_ms: elapsed.as_secs_f64() * 1000.0,
+        arg_bytes: arg_bytes.len(),
+        response_bytes: blob_res.as_ref().map(|b| b.len()).unwrap_or(0),
+        ok: blob_res.is_ok(),
+    })?;
+    let blob_res = match blob_res {
+        Ok(blob_res) => blob_res,
+        Err(e) => {
+            error!(
+                "{}: Error result: {:?}; elapsed time {:?}",
+                prefix, e, elapsed
+            );
+            return Err(e);
+        }
+    };
+    if let Some(recorder) = &ctx.cfg.trace_recorder {
+        recorder.record(&TraceEntry {
+            method: method.to_string(),
+            arg: arg_bytes,
+            response: blob_res.clone(),
+            elapsed_ms: elapsed.as_millis() as u64,
+        })?;
+    }
+    info!(
+        "{}: Ok: Response size {:?} bytes; elapsed time {:?}",
+        prefix,
+        blob_res.len(),
+        elapsed
+    );
+    let limits = &ctx.cfg.opts.limits;
+    if let Err(reason) = limits
+        .check_bytes(blob_res.len())
+        .and_then(|()| limits.check_nesting(&blob_res))
+    {
+        return Ok(rejected(method, reason));
+    }
+    let res = match call {
+        ServiceCall::Update(_, _) => match candid::Decode!(&(*blob_res), Vec<graphics::Result>) {
+            Ok(res) => Ok(res),
+            Err(candid_err) => {
+                error!("{}: Candid decoding error: {:?}", prefix, candid_err);
+                Err(IcmtError::Decode {
+                    what: format!("{} response", method),
+                    source: std::sync::Arc::new(candid_err),
+                })
+            }
+        },
+        ServiceCall::View(_, _) => match candid::Decode!(&(*blob_res), graphics::Result) {
+            Ok(res) => {
+                if log::log_enabled!(log::Level::Trace) {
+                    let mut res_log = format!("{:?}", &res);
+                    if res_log.len() > 1000 {
+                        res_log.truncate(1000);
+                        res_log.push_str("...(truncated)");
+                    }
+                    trace!(
+                        "{}: Successful decoding of graphics output: {:?}",
+                        prefix,
+                        res_log
+                    );
+                }
+                Ok(vec![res])
+            }
+            Err(candid_err) => {
+                error!("{}: Candid decoding error: {:?}", prefix, candid_err);
+                Err(IcmtError::Decode {
+                    what: format!("{} response", method),
+                    source: std::sync::Arc::new(candid_err),
+                })
+            }
+        },
+    }?;
+    match limits.check_graphics(&res) {
+        Ok(()) => Ok(res),
+        Err(reason) => {
+            drop_graphics(res);
+            Ok(rejected(method, reason))
+        }
+    }
+}
+
+/// In place of a response over the limits: an error result, with the reason.
+fn rejected(method: &str, reason: String) -> Vec<graphics::Result> {
+    warn!("Rejecting {} response: {}", method, reason);
+    vec![graphics::Result::Err(Some(format!(
+        "response rejected by ic-mt: {}",
+        reason
+    )))]
+}
+
+pub fn parse_canister_id(text: &str) -> IcmtResult<Principal> {
+    Principal::from_text(text)
+        .map_err(|e| IcmtError::Usage(format!("invalid canister id {:?}: {}", text, e)))
+}
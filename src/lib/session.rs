@@ -0,0 +1,1025 @@
+//! Terminal sessions with a canister, for `ic-mt` and other tools.
+//!
+//! A `Session` connects to a canister (the backend), sends it local input
+//! (from an `InputSource`), and shows (with a `Renderer`) and records
+//! (with each `CaptureSink`) its graphics; see `frontend`.  Build one with
+//! `Session::builder` (its setters, or a whole `SessionOptions`), then
+//! either `run` it to completion, or drive it one `step` at a time.
+//!
+//! The session is a cycle with two halves: locally, the main loop gathers
+//! input and draws; remotely, a view task and an update task make service
+//! calls (see `service`), with one shared agent.  The halves talk over
+//! (tokio) channels.
+//!
+//! When the session finishes, it cancels the view task, sends the update
+//! task any events that remain, then closes the update channel and waits
+//! (at most `SHUTDOWN_TIMEOUT`) for the outstanding update responses.
+
+use candid::Nat;
+use futures::FutureExt;
+use ic_agent::Agent;
+use ic_types::Principal;
+use log::{debug, info, trace, warn};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::task::{self, JoinHandle};
+use tokio_util::sync::CancellationToken;
+
+use crate::error::{IcmtError, IcmtResult};
+use crate::frontend::{CaptureSink, Headless, Input, InputSource, Overlay, Renderer};
+use crate::handshake::{self, Capabilities};
+use crate::iface;
+use crate::limits::{GraphicsDump, Limits};
+use crate::metrics::Metrics;
+use crate::service::{
+    create_agent, do_update_task, do_view_task, parse_canister_id, report_task_failure,
+    SharedAgent, StatusOut,
+};
+use crate::trace::{TracePlayer, TraceRecorder};
+use crate::types::{event, event_info, graphics, skip_event, ConnectionStatus, Nonces, UserKind};
+
+/// Longest wait for local input, per step, when nothing else is due sooner.
+///
+/// (The tasks wake the main loop when they respond; see `InputSource::waker`.)
+const MAX_INPUT_WAIT: Duration = Duration::from_secs(1);
+
+/// Capacity of the channels between the main loop and the tasks
+/// (the main loop has at most one request outstanding with each task).
+const CHANNEL_CAPACITY: usize = 4;
+
+/// Longest wait for the outstanding update responses, when finishing.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+type ViewRequest = (graphics::Dim, Vec<event::EventInfo>);
+type UpdateRequest = (Vec<event::EventInfo>, graphics::Request);
+
+/// Where service calls go.
+#[derive(Debug, Clone)]
+pub enum Backend {
+    /// A canister, on a replica.
+    Replica { url: String, canister_id: String },
+    /// Responses recorded in a trace file, instead of the network.
+    Trace { path: String, canister_id: String },
+}
+
+/// Identity for signing calls.
+#[derive(Debug, Clone)]
+pub enum Identity {
+    /// A fresh, random identity.
+    Random,
+    /// An identity file (PEM).
+    PemFile(String),
+}
+
+/// Session options; see the builder's setters for each.
+#[derive(Debug, Clone)]
+pub struct SessionOptions {
+    pub view_method: String,
+    pub update_method: String,
+    pub extra_args: candid::IDLArgs,
+    pub check_interface: bool,
+    pub max_retries: usize,
+    pub refresh: Option<(Duration, Duration)>,
+    pub tick_interval: Option<Duration>,
+    pub frame_size: usize,
+    pub all_graphics: bool,
+    pub mouse: bool,
+    pub limits: Limits,
+    pub record_trace: Option<String>,
+    pub metrics_path: Option<String>,
+    pub window_dim: graphics::Dim,
+}
+
+impl Default for SessionOptions {
+    /// The same defaults as `ic-mt`'s command line.
+    fn default() -> SessionOptions {
+        SessionOptions {
+            view_method: "view".to_string(),
+            update_method: "update".to_string(),
+            extra_args: candid::IDLArgs { args: vec![] },
+            check_interface: true,
+            max_retries: 20,
+            refresh: Some((Duration::from_millis(250), Duration::from_millis(4000))),
+            tick_interval: None,
+            frame_size: 6,
+            all_graphics: false,
+            mouse: false,
+            limits: Limits::default(),
+            record_trace: None,
+            metrics_path: None,
+            window_dim: graphics::Dim {
+                width: Nat::from(500),
+                height: Nat::from(400),
+            },
+        }
+    }
+}
+
+/// Connection context: IC agent object, for server calls, and configuration info.
+pub struct ConnectCtx {
+    pub cfg: ConnectCfg,
+    pub agent: Agent,
+    pub canister_id: Principal,
+}
+
+/// Connection configuration
+#[derive(Debug, Clone)]
+pub struct ConnectCfg {
+    pub opts: SessionOptions,
+    pub identity: Identity,
+    pub canister_id: String,
+    pub replica_url: String,
+    pub user_kind: UserKind,
+    pub nonces: Nonces,
+    pub metrics: Metrics,
+    pub trace_recorder: Option<TraceRecorder>,
+    pub trace_player: Option<TracePlayer>,
+    /// Capabilities negotiated with the canister (see `handshake`).
+    pub caps: Capabilities,
+}
+
+/// Outcome of a session step.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    Continue,
+    Done,
+}
+
+/// Builds a session.
+pub struct SessionBuilder {
+    opts: SessionOptions,
+    backend: Option<Backend>,
+    identity: Identity,
+    user_kind: Option<UserKind>,
+    nonces: Option<Nonces>,
+    renderer: Option<Box<dyn Renderer>>,
+    input: Option<Box<dyn InputSource>>,
+    captures: Vec<Box<dyn CaptureSink>>,
+}
+
+/// A session with a canister.
+pub struct Session {
+    ctx: ConnectCtx,
+    renderer: Box<dyn Renderer>,
+    input: Box<dyn InputSource>,
+    captures: Vec<Box<dyn CaptureSink>>,
+
+    is_live: bool,
+    /// Spectators are live (they see local input), but send no events.
+    is_spectator: bool,
+    replay_events: Vec<event::EventInfo>,
+    frame_size: usize,
+    window_dim: graphics::Dim,
+
+    view_events: Vec<event::EventInfo>,
+    update_events: Vec<event::EventInfo>,
+    dump_events: Vec<event::EventInfo>,
+    dump_graphics: GraphicsDump,
+
+    conn_status: ConnectionStatus,
+    status_in: mpsc::UnboundedReceiver<ConnectionStatus>,
+    last_view: Option<graphics::Result>,
+
+    update_in: mpsc::Receiver<Vec<graphics::Result>>,
+    /// Closed (`None`) once the session finishes, to stop the update task.
+    update_out: Option<mpsc::Sender<UpdateRequest>>,
+    view_in: mpsc::Receiver<(graphics::Result, Instant)>,
+    view_out: mpsc::Sender<ViewRequest>,
+    /// Cancels both tasks; its child cancels only the view task.
+    cancel: CancellationToken,
+    view_cancel: CancellationToken,
+    tasks: Vec<JoinHandle<()>>,
+
+    quit_request: bool, // user has requested to quit: shut down gracefully.
+    dirty_flag: bool,   // more events ready for view task
+    ready_flag: bool,   // view task is ready for more events
+    done: bool,
+
+    update_requests: Nat,
+    update_responses: Nat,
+    view_requests: Nat,
+    view_responses: Nat,
+    replay_event_counter: Nat,
+
+    hud_visible: bool, // toggled by F1 (in the window)
+    refresh: Option<Refresh>,
+    view_is_refresh: bool, // last view request was only to refresh
+    tick_interval: Option<Duration>,
+    last_tick: Instant,
+}
+
+/// Idle view refresh, backing off (doubling its interval) while the view stays unchanged.
+struct Refresh {
+    min: Duration,
+    max: Duration,
+    interval: Duration,
+    last_request: Instant,
+}
+
+impl Refresh {
+    fn new(min: Duration, max: Duration) -> Refresh {
+        Refresh {
+            min,
+            max,
+            interval: min,
+            last_request: Instant::now(),
+        }
+    }
+
+    fn is_due(&self) -> bool {
+        self.last_request.elapsed() >= self.interval
+    }
+
+    fn until_due(&self) -> Duration {
+        self.interval
+            .checked_sub(self.last_request.elapsed())
+            .unwrap_or(Duration::from_millis(0))
+    }
+
+    fn requested(&mut self) {
+        self.last_request = Instant::now();
+    }
+
+    fn responded(&mut self, changed: bool) {
+        self.interval = if changed {
+            self.min
+        } else {
+            (self.interval * 2).min(self.max)
+        };
+    }
+}
+
+impl Session {
+    /// A builder, with the default options.
+    pub fn builder() -> SessionBuilder {
+        SessionBuilder {
+            opts: SessionOptions::default(),
+            backend: None,
+            identity: Identity::Random,
+            user_kind: None,
+            nonces: None,
+            renderer: None,
+            input: None,
+            captures: vec![],
+        }
+    }
+}
+
+impl SessionBuilder {
+    /// All of the options at once (replacing those set so far).
+    pub fn options(mut self, opts: SessionOptions) -> Self {
+        self.opts = opts;
+        self
+    }
+
+    /// Names of the canister's view (query) and update methods (default: `view`, `update`).
+    pub fn methods(mut self, view_method: &str, update_method: &str) -> Self {
+        self.opts.view_method = view_method.to_string();
+        self.opts.update_method = update_method.to_string();
+        self
+    }
+
+    /// Extra leading arguments for view and update calls (default: none).
+    pub fn extra_args(mut self, extra_args: candid::IDLArgs) -> Self {
+        self.opts.extra_args = extra_args;
+        self
+    }
+
+    /// Check the canister's interface against the terminal's, at startup (default: yes).
+    pub fn check_interface(mut self, check: bool) -> Self {
+        self.opts.check_interface = check;
+        self
+    }
+
+    /// Give up on a failing service call after this many retries (0 = never; default: 20).
+    ///
+    /// (Retried updates may deliver their events twice; see `service_call_retry`.)
+    pub fn max_retries(mut self, max_retries: usize) -> Self {
+        self.opts.max_retries = max_retries;
+        self
+    }
+
+    /// Re-query the view when idle, backing off from the shorter interval
+    /// to the longer one while it stays unchanged (`None` = never).
+    pub fn refresh(mut self, refresh: Option<(Duration, Duration)>) -> Self {
+        self.opts.refresh = refresh;
+        self
+    }
+
+    /// Send tick events at this interval (`None` = never, the default).
+    pub fn tick(mut self, interval: Option<Duration>) -> Self {
+        self.opts.tick_interval = interval;
+        self
+    }
+
+    /// Events per update request, when replaying (default: 6).
+    pub fn frame_size(mut self, frame_size: usize) -> Self {
+        self.opts.frame_size = frame_size;
+        self
+    }
+
+    /// Request (and capture) the graphics of every update (default: no).
+    pub fn all_graphics(mut self, all_graphics: bool) -> Self {
+        self.opts.all_graphics = all_graphics;
+        self
+    }
+
+    /// Send local mouse presses to the canister (default: no).
+    pub fn mouse(mut self, mouse: bool) -> Self {
+        self.opts.mouse = mouse;
+        self
+    }
+
+    /// Limits on each response (and on the graphics buffered for capture).
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.opts.limits = limits;
+        self
+    }
+
+    /// Record every service call to this trace file.
+    pub fn record_trace(mut self, path: &str) -> Self {
+        self.opts.record_trace = Some(path.to_string());
+        self
+    }
+
+    /// Export a record of every service call to this file (see `Metrics`).
+    pub fn metrics(mut self, path: &str) -> Self {
+        self.opts.metrics_path = Some(path.to_string());
+        self
+    }
+
+    /// Window size, for the default renderer (and input).
+    pub fn window_dim(mut self, dim: graphics::Dim) -> Self {
+        self.opts.window_dim = dim;
+        self
+    }
+
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    pub fn identity(mut self, identity: Identity) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    /// The local user, a replay, or a spectator.
+    pub fn user(mut self, user_kind: UserKind) -> Self {
+        self.user_kind = Some(user_kind);
+        self
+    }
+
+    /// Event nonces (default: a fresh session).
+    pub fn nonces(mut self, nonces: Nonces) -> Self {
+        self.nonces = Some(nonces);
+        self
+    }
+
+    /// Graphics output (default: none).
+    pub fn renderer(mut self, renderer: Box<dyn Renderer>) -> Self {
+        self.renderer = Some(renderer);
+        self
+    }
+
+    /// Local input (default: none).
+    pub fn input(mut self, input: Box<dyn InputSource>) -> Self {
+        self.input = Some(input);
+        self
+    }
+
+    /// Add a capture sink.
+    pub fn capture(mut self, sink: Box<dyn CaptureSink>) -> Self {
+        self.captures.push(sink);
+        self
+    }
+
+    /// Connect to the canister: check its interface, negotiate
+    /// capabilities, and start the view and update tasks.
+    pub async fn build(self) -> IcmtResult<Session> {
+        let opts = self.opts;
+        let (replica_url, canister_id, trace_player) = match self.backend {
+            Some(Backend::Replica { url, canister_id }) => (url, canister_id, None),
+            Some(Backend::Trace { path, canister_id }) => {
+                let player = TracePlayer::load(&path)?;
+                ("http://localhost".to_string(), canister_id, Some(player))
+            }
+            None => return Err(IcmtError::Usage("no backend for session".to_string())),
+        };
+        let trace_recorder = match &opts.record_trace {
+            Some(path) => Some(TraceRecorder::create(path)?),
+            None => None,
+        };
+        let metrics = Metrics::new(opts.metrics_path.as_deref())?;
+        let nonces = match self.nonces {
+            Some(nonces) => nonces,
+            None => Nonces::new()?,
+        };
+        let user_kind = self
+            .user_kind
+            .ok_or_else(|| IcmtError::Usage("no user for session".to_string()))?;
+        let cfg = ConnectCfg {
+            opts,
+            identity: self.identity,
+            canister_id,
+            replica_url,
+            user_kind,
+            trace_recorder,
+            trace_player,
+            nonces,
+            metrics,
+            caps: Capabilities::all(),
+        };
+
+        let canister_id = parse_canister_id(&cfg.canister_id)?;
+        let agent = create_agent(&cfg).await?;
+
+        if cfg.opts.check_interface && cfg.trace_player.is_none() {
+            match iface::fetch_interface(&agent, &canister_id).await? {
+                Some(did) => iface::check_interface(
+                    &did,
+                    &cfg.opts.view_method,
+                    &cfg.opts.update_method,
+                    cfg.opts.extra_args.args.len(),
+                )
+                .map_err(IcmtError::Interface)?,
+                None => warn!(
+                    "Canister {} does not provide its interface; not checking it.",
+                    canister_id
+                ),
+            }
+        }
+
+        info!("Connecting to IC canister: {}", canister_id);
+        let mut ctx = ConnectCtx {
+            cfg,
+            canister_id,
+            agent,
+        };
+        ctx.cfg.caps = handshake::negotiate(&ctx).await?;
+        trace!("{:?}", ctx.cfg);
+
+        let renderer = self
+            .renderer
+            .unwrap_or_else(|| Box::new(Headless::new(ctx.cfg.opts.window_dim.clone())));
+        let input = self
+            .input
+            .unwrap_or_else(|| Box::new(Headless::new(ctx.cfg.opts.window_dim.clone())));
+        Session::start(ctx, renderer, input, self.captures).await
+    }
+}
+
+impl Session {
+    async fn start(
+        ctx: ConnectCtx,
+        mut renderer: Box<dyn Renderer>,
+        input: Box<dyn InputSource>,
+        mut captures: Vec<Box<dyn CaptureSink>>,
+    ) -> IcmtResult<Session> {
+        renderer.negotiated(&ctx.cfg.caps);
+        for c in captures.iter_mut() {
+            c.negotiated(&ctx.cfg.caps);
+        }
+        let (is_live, is_spectator, replay_events) = match &ctx.cfg.user_kind {
+            UserKind::Replay(evs) => {
+                let (evs, dropped): (Vec<_>, Vec<_>) = evs
+                    .iter()
+                    .cloned()
+                    .partition(|ev| ctx.cfg.caps.supports_event(&ev.event));
+                if dropped.len() > 0 {
+                    warn!(
+                        "Not replaying {} event(s) of kinds that the canister does not support.",
+                        dropped.len()
+                    );
+                }
+                (false, false, evs)
+            }
+            UserKind::Local(_) => (true, false, vec![]),
+            UserKind::Spectator => (true, true, vec![]),
+        };
+        let frame_size = ctx.cfg.opts.frame_size;
+        let refresh = match ctx.cfg.opts.refresh {
+            Some((min, max)) if is_live => Some(Refresh::new(min, max.max(min))),
+            _ => None,
+        };
+
+        // Tick events, when enabled (and supported by the canister).
+        let tick_interval = match ctx.cfg.opts.tick_interval {
+            Some(interval) if is_live && !is_spectator => {
+                if ctx.cfg.caps.supports_event(&event::Event::Tick(Nat::from(0))) {
+                    Some(interval)
+                } else {
+                    warn!("Canister does not support tick events; not sending them.");
+                    None
+                }
+            }
+            _ => None,
+        };
+
+        let window_dim = renderer.dim();
+
+        let mut view_events = vec![];
+        let mut update_events = vec![];
+        let mut dump_events = vec![];
+
+        if is_live && !is_spectator {
+            let ev0 = skip_event(&ctx);
+            view_events = vec![];
+            dump_events = vec![ev0.clone()];
+            update_events = vec![ev0.clone()];
+        };
+
+        // Both tasks report their connection status (and failures) to the main loop,
+        // and wake it (from its wait for input) to take their responses.
+        let (status_tx, status_in) = mpsc::unbounded_channel::<ConnectionStatus>();
+        let status_out = StatusOut::new(status_tx, input.waker());
+        let shared_agent = SharedAgent::new(ctx.agent.clone());
+        let task_ctx = || ConnectCtx {
+            cfg: ctx.cfg.clone(),
+            canister_id: ctx.canister_id.clone(),
+            agent: ctx.agent.clone(),
+        };
+        let cancel = CancellationToken::new();
+        let view_cancel = cancel.child_token();
+        let mut tasks = vec![];
+
+        // Interaction cycle as two halves (local/remote); the remote half is
+        // two tasks, each with a request channel and a response channel.
+        let (update_out, remote_in) = mpsc::channel::<UpdateRequest>(CHANNEL_CAPACITY);
+        let (remote_out, update_in) = mpsc::channel::<Vec<graphics::Result>>(CHANNEL_CAPACITY);
+        if !is_spectator {
+            tasks.push(task::spawn(report_task_failure(
+                "update",
+                status_out.clone(),
+                do_update_task(
+                    task_ctx(),
+                    shared_agent.clone(),
+                    remote_in,
+                    remote_out,
+                    status_out.clone(),
+                    cancel.clone(),
+                ),
+            )));
+        }
+        let req = if ctx.cfg.opts.all_graphics {
+            graphics::Request::All(window_dim.clone())
+        } else {
+            graphics::Request::None
+        };
+        if is_live && !is_spectator {
+            update_out.send((update_events, req)).await?;
+            update_events = vec![];
+        }
+
+        let (view_out, remote_in) = mpsc::channel::<ViewRequest>(CHANNEL_CAPACITY);
+        let (remote_out, view_in) =
+            mpsc::channel::<(graphics::Result, Instant)>(CHANNEL_CAPACITY);
+        tasks.push(task::spawn(report_task_failure(
+            "view",
+            status_out.clone(),
+            do_view_task(
+                task_ctx(),
+                shared_agent,
+                remote_in,
+                remote_out,
+                status_out,
+                view_cancel.clone(),
+            ),
+        )));
+        view_out.send((window_dim.clone(), view_events)).await?;
+        view_events = vec![];
+
+        Ok(Session {
+            ctx,
+            renderer,
+            input,
+            captures,
+            is_live,
+            is_spectator,
+            replay_events,
+            frame_size,
+            window_dim,
+            view_events,
+            update_events,
+            dump_events,
+            dump_graphics: GraphicsDump::new(ctx.cfg.opts.limits.max_captured_elements),
+            conn_status: ConnectionStatus::Connected,
+            status_in,
+            last_view: None,
+            update_in,
+            update_out: Some(update_out),
+            view_in,
+            view_out,
+            cancel,
+            view_cancel,
+            tasks,
+            quit_request: false,
+            dirty_flag: true,
+            ready_flag: true,
+            done: false,
+            update_requests: Nat::from(1), // (already one).
+            update_responses: Nat::from(0),
+            view_requests: Nat::from(1), // (already one).
+            view_responses: Nat::from(0),
+            replay_event_counter: Nat::from(0),
+            hud_visible: false,
+            refresh,
+            view_is_refresh: false,
+            tick_interval,
+            last_tick: Instant::now(),
+        })
+    }
+
+    /// The session's connection context (configuration, agent and canister).
+    pub fn ctx(&self) -> &ConnectCtx {
+        &self.ctx
+    }
+
+    /// Ask the session to quit (gracefully, as if the user asked).
+    pub fn quit(&mut self) {
+        self.quit_request = true;
+    }
+
+    /// Run the session until it is done.
+    ///
+    /// Each step waits for local input, blocking its thread (as the front
+    /// ends do), so run the session on a multi-threaded tokio runtime
+    /// (the default for `#[tokio::main]`), which moves its other tasks
+    /// off that thread while it waits.
+    pub async fn run(mut self) -> IcmtResult<()> {
+        while self.step().await? == Step::Continue {}
+        Ok(())
+    }
+
+    fn overlay(&self) -> Overlay {
+        Overlay {
+            status: self.conn_status.clone(),
+            hud: if self.hud_visible {
+                // local events not yet acknowledged by an update response.
+                let queue_depth = self.view_events.len() + self.update_events.len();
+                Some((self.ctx.cfg.metrics.summary(), queue_depth))
+            } else {
+                None
+            },
+        }
+    }
+
+    fn redraw(&mut self) -> IcmtResult<()> {
+        let overlay = self.overlay();
+        self.renderer.redraw(&overlay)
+    }
+
+    /// One step of the session's main loop: attend to connection status,
+    /// local input (or the next replayed events), and the view and update tasks.
+    ///
+    /// Needs a multi-threaded tokio runtime; see `run`.
+    pub async fn step(&mut self) -> IcmtResult<Step> {
+        if self.done {
+            return Ok(Step::Done);
+        }
+        /* attend to connection status, as reported by the tasks */
+        while let Some(Some(status)) = self.status_in.recv().now_or_never() {
+            self.connection_status(status)?;
+        }
+        if let Some(interval) = self.tick_interval {
+            let elapsed = self.last_tick.elapsed();
+            if elapsed >= interval && !self.quit_request {
+                self.last_tick = Instant::now();
+                let ev = event_info(
+                    &self.ctx,
+                    event::Event::Tick(Nat::from(elapsed.as_millis() as u64)),
+                );
+                trace!("Tick {:?}", elapsed);
+                self.dirty_flag = true;
+                self.view_events.push(ev.clone());
+                self.dump_events.push(ev);
+            }
+        }
+        if self.is_live {
+            // (waiting for input blocks this thread; see `run`.)
+            let wait = self.input_wait();
+            let input = &mut self.input;
+            match task::block_in_place(|| input.next_input(wait))? {
+                None => (),
+                Some(Input::ToggleHud) => {
+                    self.hud_visible = !self.hud_visible;
+                    self.redraw()?;
+                    return Ok(Step::Continue);
+                }
+                Some(Input::Redraw) => {
+                    self.redraw()?;
+                    return Ok(Step::Continue);
+                }
+                Some(Input::Event(event)) => {
+                    if !self.accepts(&event) {
+                        return Ok(Step::Continue);
+                    }
+                    self.local_event(event)?;
+                }
+            }
+        } else {
+            self.replay_next().await?;
+        }
+        if self.quit_request {
+            self.finish().await?;
+            return Ok(Step::Done);
+        }
+        self.attend_view().await?;
+        self.attend_update().await?;
+        Ok(Step::Continue)
+    }
+
+    fn connection_status(&mut self, status: ConnectionStatus) -> IcmtResult<()> {
+        if status == self.conn_status {
+            return Ok(());
+        }
+        info!("Connection status: {}", status);
+        self.conn_status = status;
+        self.redraw()?;
+        if let ConnectionStatus::Failed(ref e) = self.conn_status {
+            warn!("Lost connection: {}", e);
+            warn!("Cannot recover; quitting...");
+            self.quit_request = true;
+        }
+        Ok(())
+    }
+
+    fn update_sender(&self) -> IcmtResult<&mpsc::Sender<UpdateRequest>> {
+        self.update_out
+            .as_ref()
+            .ok_or_else(|| IcmtError::Channel("update channel is closed".to_string()))
+    }
+
+    /// How long to wait for local input: until the next tick or refresh is
+    /// due, and not at all while a view request is waiting to be sent.
+    fn input_wait(&self) -> Duration {
+        let zero = Duration::from_millis(0);
+        if self.quit_request || (self.dirty_flag && self.ready_flag) {
+            return zero;
+        }
+        let mut wait = MAX_INPUT_WAIT;
+        if let Some(interval) = self.tick_interval {
+            wait = wait.min(interval.checked_sub(self.last_tick.elapsed()).unwrap_or(zero));
+        }
+        if let Some(refresh) = &self.refresh {
+            if self.ready_flag {
+                wait = wait.min(refresh.until_due());
+            }
+        }
+        wait
+    }
+
+    /// Does the session send this (local) event?
+    fn accepts(&self, event: &event::Event) -> bool {
+        if *event == event::Event::Quit {
+            return true;
+        }
+        if let event::Event::MouseDown(_) = event {
+            if !self.ctx.cfg.opts.mouse {
+                trace!("Mouse input is off; not sending mouseDown event");
+                return false;
+            }
+        }
+        if !self.ctx.cfg.caps.supports_event(event) {
+            debug!("Dropping {} event: canister does not support it.", event.kind());
+            return false;
+        }
+        if self.is_spectator && !matches!(event, event::Event::WindowSize(_)) {
+            trace!("Spectator: not sending {} event", event.kind());
+            return false;
+        }
+        true
+    }
+
+    fn local_event(&mut self, event: event::Event) -> IcmtResult<()> {
+        match event {
+            event::Event::Skip | event::Event::Tick(_) => {
+                // ignore (ticks are not local input)
+            }
+            event::Event::Quit => {
+                info!("Quit");
+                info!("Begin: Quitting...");
+                if !self.is_spectator {
+                    info!("Waiting for next update response...");
+                }
+                self.quit_request = true;
+            }
+            event::Event::WindowSize(new_dim) => {
+                info!("WindowSize {:?}", new_dim);
+                self.dirty_flag = true;
+                if !self.is_spectator {
+                    let skip = skip_event(&self.ctx);
+                    self.view_events.push(skip.clone());
+                    self.dump_events.push(skip);
+                }
+                for c in self.captures.iter_mut() {
+                    c.resize(&self.window_dim, &new_dim)?;
+                }
+                self.window_dim = new_dim;
+            }
+            event => {
+                info!("{:?}", event);
+                self.dirty_flag = true;
+                let ev = event_info(&self.ctx, event);
+                self.view_events.push(ev.clone());
+                self.dump_events.push(ev);
+            }
+        };
+        Ok(())
+    }
+
+    async fn replay_next(&mut self) -> IcmtResult<()> {
+        if self.replay_events.len() == 0 {
+            self.quit_request = true
+        } else {
+            let replay_events_now = if self.replay_events.len() > self.frame_size {
+                let tl = self.replay_events.split_off(self.frame_size);
+                std::mem::replace(&mut self.replay_events, tl)
+            } else {
+                std::mem::replace(&mut self.replay_events, vec![])
+            };
+            self.replay_event_counter += replay_events_now.len();
+            info!(
+                "Replaying {} event(s), with {} remaining (using frame_size {}); {} so far...",
+                replay_events_now.len(),
+                self.replay_events.len(),
+                self.frame_size,
+                self.replay_event_counter
+            );
+            self.dump_events.extend(replay_events_now.clone());
+            self.update_sender()?
+                .send((
+                    replay_events_now,
+                    graphics::Request::All(self.window_dim.clone()),
+                ))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Stop the tasks, draining the outstanding updates, and finish the captures.
+    async fn finish(&mut self) -> IcmtResult<()> {
+        self.done = true;
+        // Done drawing: close the renderer now (a terminal's leaves its
+        // alternate screen), so that the captures' messages show.
+        self.renderer = Box::new(Headless::new(self.window_dim.clone()));
+        info!("Stopping view task...");
+        self.view_cancel.cancel();
+
+        if self.is_live && !self.is_spectator && self.view_events.len() > 0 {
+            let req = if self.ctx.cfg.opts.all_graphics {
+                graphics::Request::All(self.window_dim.clone())
+            } else {
+                graphics::Request::None
+            };
+            let events = std::mem::replace(&mut self.view_events, vec![]);
+            // (the update task is gone when its connection failed.)
+            let _ = self.update_sender()?.send((events, req)).await;
+        }
+        // closing the channel stops the update task, once its requests are done.
+        self.update_out = None;
+        if !self.is_spectator {
+            info!("Waiting for outstanding update responses...");
+            let update_in = &mut self.update_in;
+            let dump_graphics = &mut self.dump_graphics;
+            let drain = async {
+                while let Some(graphics) = update_in.recv().await {
+                    info!("graphics.len() = {}", graphics.len());
+                    dump_graphics.extend(graphics);
+                }
+            };
+            match tokio::time::timeout(SHUTDOWN_TIMEOUT, drain).await {
+                Ok(()) => info!("Update responses done."),
+                Err(_) => warn!(
+                    "Update responses still outstanding after {:?}; not waiting for them.",
+                    SHUTDOWN_TIMEOUT
+                ),
+            }
+        }
+        self.cancel.cancel();
+        for task in self.tasks.drain(..) {
+            let _ = task.await;
+        }
+        while let Some(Some(status)) = self.status_in.recv().now_or_never() {
+            self.connection_status(status)?;
+        }
+
+        for c in self.captures.iter_mut() {
+            c.finish(
+                &self.window_dim,
+                &self.dump_events,
+                &self.dump_graphics.results,
+            )?;
+        }
+        if let ConnectionStatus::Failed(e) = &self.conn_status {
+            return Err(IcmtError::String(e.clone()));
+        }
+        info!("All done.");
+        Ok(())
+    }
+
+    /* attend to view task */
+    async fn attend_view(&mut self) -> IcmtResult<()> {
+        match self.view_in.recv().now_or_never() {
+            Some(Some((rr, received))) => {
+                self.view_responses += 1;
+                info!("view_responses = {}", self.view_responses);
+
+                let changed = self.last_view.as_ref() != Some(&rr);
+                if let Some(refresh) = &mut self.refresh {
+                    refresh.responded(changed);
+                }
+                // An unchanged refresh needs no redraw (nor another captured frame).
+                if changed || !self.view_is_refresh {
+                    let overlay = self.overlay();
+                    self.renderer.render(&self.window_dim, &rr, &overlay)?;
+                    self.ctx.cfg.metrics.record_frame();
+                    self.ctx.cfg.metrics.record_present(received.elapsed());
+                    for c in self.captures.iter_mut() {
+                        c.frame(&self.window_dim, &rr)?;
+                    }
+                }
+                self.last_view = Some(rr);
+
+                self.ready_flag = true;
+            }
+            Some(None) => { /* view task has stopped (and reported why) */ }
+            None => { /* not ready; do nothing */ }
+        };
+
+        /* when idle, refresh the view (to show other users' changes) */
+        let mut refresh_only = false;
+        if let Some(refresh) = &self.refresh {
+            if !self.dirty_flag && self.ready_flag && refresh.is_due() {
+                self.dirty_flag = true;
+                refresh_only = true;
+            }
+        }
+
+        if self.dirty_flag && self.ready_flag {
+            self.dirty_flag = false;
+            self.ready_flag = false;
+            self.view_is_refresh = refresh_only;
+            if let Some(refresh) = &mut self.refresh {
+                refresh.requested();
+            }
+            let mut events = self.update_events.clone();
+            events.append(&mut (self.view_events.clone()));
+
+            self.view_out.send((self.window_dim.clone(), events)).await?;
+
+            self.view_requests += 1;
+            debug!("view_requests = {}", self.view_requests);
+        }
+        Ok(())
+    }
+
+    /* attend to update task (spectators have none) */
+    async fn attend_update(&mut self) -> IcmtResult<()> {
+        if self.is_spectator {
+            return Ok(());
+        }
+        let update_msg = if self.is_live {
+            self.update_in.recv().now_or_never()
+        } else {
+            // a replay awaits each response (or the connection's failure).
+            tokio::select! {
+                graphics = self.update_in.recv() => Some(graphics),
+                Some(status) = self.status_in.recv() => {
+                    self.connection_status(status)?;
+                    None
+                }
+            }
+        };
+        match update_msg {
+            Some(Some(graphics)) => {
+                debug!("graphics.len() = {}", graphics.len());
+                self.dump_graphics.extend(graphics);
+                self.update_responses += 1;
+                debug!("update_responses = {}", self.update_responses);
+                if self.is_live {
+                    /* send the local events in the view buffer */
+                    let req = if self.ctx.cfg.opts.all_graphics {
+                        graphics::Request::All(self.window_dim.clone())
+                    } else {
+                        graphics::Request::None
+                    };
+                    self.update_sender()?
+                        .send((self.view_events.clone(), req))
+                        .await?;
+                };
+                self.update_requests += 1;
+                debug!("update_requests = {}", self.update_requests);
+                self.update_events = std::mem::replace(&mut self.view_events, vec![]);
+                self.dirty_flag = true;
+            }
+            Some(None) => {
+                /* update task has stopped (and reported why) */
+                warn!("Update task has stopped; quitting...");
+                self.quit_request = true;
+            }
+            None => { /* not ready; do nothing */ }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,228 @@
+//! Traces of raw canister traffic: recording, and offline playback.
+//!
+//! A trace file holds one JSON record per line; each record gives the
+//! method name, the Candid-encoded argument and response blobs (as hex)
+//! and the elapsed time of the original call.
+//!
+//! Playback matches each call with a recorded one by its argument, with
+//! the events' nonces, session ids and timestamps cleared, since those
+//! differ on every run.
+
+use candid::de::IDLDeserialize;
+use candid::parser::value::IDLValue;
+use candid::ser::IDLBuilder;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::sync::{Arc, Mutex};
+
+use crate::error::{io_err, IcmtError, IcmtResult};
+use crate::types::{event, graphics};
+
+/// One recorded service call.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub method: String,
+    #[serde(with = "hex_blob")]
+    pub arg: Vec<u8>,
+    #[serde(with = "hex_blob")]
+    pub response: Vec<u8>,
+    pub elapsed_ms: u64,
+}
+
+mod hex_blob {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &Vec<u8>, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+        let text = String::deserialize(d)?;
+        hex::decode(&text).map_err(D::Error::custom)
+    }
+}
+
+/// How to read a call's argument, to match it with recorded ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgShape {
+    /// Compare the argument bytes as they are.
+    Raw,
+    /// A view call, after this many extra (leading) arguments.
+    View(usize),
+    /// An update call, after this many extra (leading) arguments.
+    Update(usize),
+}
+
+impl ArgShape {
+    /// The argument, re-encoded with the events' nonces, session ids and
+    /// timestamps cleared; `None` when it does not decode with this shape.
+    fn normalize(self, arg: &[u8]) -> Option<Vec<u8>> {
+        let extra_args = match self {
+            ArgShape::Raw => return Some(arg.to_vec()),
+            ArgShape::View(n) | ArgShape::Update(n) => n,
+        };
+        let normalize = || -> Result<Vec<u8>, candid::Error> {
+            let mut de = IDLDeserialize::new(arg)?;
+            let mut builder = IDLBuilder::new();
+            for _ in 0..extra_args {
+                let value: IDLValue = de.get_value()?;
+                builder.value_arg(&value)?;
+            }
+            if let ArgShape::View(_) = self {
+                let dim: graphics::Dim = de.get_value()?;
+                let evs: Vec<event::EventInfo> = de.get_value()?;
+                builder.arg(&dim)?.arg(&clear_events(evs))?;
+            } else {
+                let evs: Vec<event::EventInfo> = de.get_value()?;
+                let req: graphics::Request = de.get_value()?;
+                builder.arg(&clear_events(evs))?.arg(&req)?;
+            }
+            de.done()?;
+            builder.serialize_to_vec()
+        };
+        normalize().ok()
+    }
+}
+
+fn clear_events(mut evs: Vec<event::EventInfo>) -> Vec<event::EventInfo> {
+    for ev in evs.iter_mut() {
+        ev.nonce = None;
+        ev.session_id = None;
+        ev.date_time_utc = String::new();
+        ev.date_time_local = String::new();
+    }
+    evs
+}
+
+/// Appends every service call to a trace file.
+#[derive(Clone)]
+pub struct TraceRecorder {
+    path: String,
+    file: Arc<Mutex<File>>,
+}
+
+impl std::fmt::Debug for TraceRecorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "TraceRecorder({:?})", self.path)
+    }
+}
+
+impl TraceRecorder {
+    pub fn create(path: &str) -> IcmtResult<TraceRecorder> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(io_err(path))?;
+        info!("Recording service calls to trace file {}", path);
+        Ok(TraceRecorder {
+            path: path.to_string(),
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    pub fn record(&self, entry: &TraceEntry) -> IcmtResult<()> {
+        let line = serde_json::to_string(entry).map_err(|e| e.to_string())?;
+        let mut file = self.file.lock().unwrap();
+        writeln!(file, "{}", line)?;
+        file.flush()?;
+        Ok(())
+    }
+}
+
+/// Serves service responses from a recorded trace, instead of the network.
+#[derive(Clone)]
+pub struct TracePlayer {
+    path: String,
+    entries: Arc<Mutex<Vec<Recorded>>>,
+}
+
+struct Recorded {
+    entry: TraceEntry,
+    /// Whether it has been served yet.
+    served: bool,
+    /// Its normalized argument, once computed (see `ArgShape::normalize`).
+    key: Option<Option<Vec<u8>>>,
+}
+
+impl std::fmt::Debug for TracePlayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "TracePlayer({:?})", self.path)
+    }
+}
+
+impl TracePlayer {
+    pub fn load(path: &str) -> IcmtResult<TracePlayer> {
+        let file = File::open(path).map_err(io_err(path))?;
+        let mut entries = vec![];
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: TraceEntry = serde_json::from_str(&line)
+                .map_err(|e| format!("{}:{}: bad trace record: {}", path, i + 1, e))?;
+            entries.push(Recorded {
+                entry,
+                served: false,
+                key: None,
+            });
+        }
+        info!("Loaded {} service calls from trace file {}", entries.len(), path);
+        Ok(TracePlayer {
+            path: path.to_string(),
+            entries: Arc::new(Mutex::new(entries)),
+        })
+    }
+
+    /// Response for a request, matched by content.
+    ///
+    /// Prefers the first unserved entry with the same method and argument
+    /// (as `shape` normalizes it), then any served entry with those
+    /// (queries may repeat), and finally the next unserved entry for the
+    /// same method, in trace order.
+    pub fn respond(&self, method: &str, arg: &[u8], shape: ArgShape) -> IcmtResult<Vec<u8>> {
+        let mut entries = self.entries.lock().unwrap();
+        let key = shape.normalize(arg);
+        if key.is_some() {
+            for rec in entries.iter_mut() {
+                if rec.entry.method == method && rec.key.is_none() {
+                    rec.key = Some(shape.normalize(&rec.entry.arg));
+                }
+            }
+        }
+        let same = |rec: &Recorded| {
+            rec.entry.method == method
+                && (rec.entry.arg.as_slice() == arg
+                    || (key.is_some() && rec.key.as_ref() == Some(&key)))
+        };
+        let pos = entries
+            .iter()
+            .position(|rec| !rec.served && same(rec))
+            .or_else(|| entries.iter().position(|rec| same(rec)))
+            .or_else(|| {
+                let pos = entries
+                    .iter()
+                    .position(|rec| !rec.served && rec.entry.method == method);
+                if pos.is_some() {
+                    warn!(
+                        "Trace {}: no recorded {} call has this argument; using the next one in order.",
+                        self.path, method
+                    );
+                };
+                pos
+            });
+        match pos {
+            Some(pos) => {
+                entries[pos].served = true;
+                Ok(entries[pos].entry.response.clone())
+            }
+            None => Err(IcmtError::String(format!(
+                "trace {} has no response for {} call",
+                self.path, method
+            ))),
+        }
+    }
+}
@@ -0,0 +1,287 @@
+//! Text-terminal (TTY) front end: graphics as 24-bit ANSI colors, two pixels
+//! per character cell (a Unicode upper half block, with the upper pixel as
+//! its foreground color and the lower pixel as its background color), and
+//! input from the terminal's keyboard and mouse.
+//!
+//! The canister sees one pixel per column, and two per row; the terminal's
+//! last row shows the connection status.
+
+use candid::Nat;
+use crossterm::event::{
+    self as term, Event as TtyEvent, KeyCode, KeyEvent, KeyModifiers, MouseEventKind,
+};
+use crossterm::{cursor, queue, style, terminal};
+use log::{info, trace};
+use std::io::{Stdout, Write};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+use crate::display::DisplayList;
+use crate::error::{IcmtError, IcmtResult};
+use crate::frontend::{Input, InputSource, Overlay, Renderer, Waker};
+use crate::handshake::Capabilities;
+use crate::hud::hud_lines;
+use crate::types::{event, graphics, nat_ceil, ConnectionStatus};
+
+const UPPER_HALF_BLOCK: char = '\u{2580}';
+
+/// Terminal output.
+pub struct TtyRenderer {
+    out: Stdout,
+    dim: graphics::Dim,
+    /// The last frame's pixels (row by row), for redrawing it.
+    pixels: Vec<(u8, u8, u8)>,
+    caps: Capabilities,
+}
+
+/// Terminal input, read by a thread of its own (so that it can be woken).
+pub struct TtyInput {
+    dim: graphics::Dim,
+    input_in: mpsc::Receiver<Option<TtyEvent>>,
+    input_out: mpsc::Sender<Option<TtyEvent>>,
+}
+
+/// The canister's dimension for a terminal with the given columns and rows.
+fn dim_of_size(cols: u16, rows: u16) -> graphics::Dim {
+    graphics::Dim {
+        width: Nat::from(cols as u64),
+        height: Nat::from(2 * rows.saturating_sub(1) as u64),
+    }
+}
+
+/// Take over the terminal (raw mode, alternate screen, mouse capture).
+pub fn open() -> IcmtResult<(TtyRenderer, TtyInput)> {
+    let mut out = std::io::stdout();
+    terminal::enable_raw_mode()?;
+    crossterm::execute!(
+        out,
+        terminal::EnterAlternateScreen,
+        term::EnableMouseCapture,
+        cursor::Hide
+    )?;
+    let (cols, rows) = terminal::size()?;
+    let dim = dim_of_size(cols, rows);
+
+    let (input_out, input_in) = mpsc::channel();
+    let reader_out = input_out.clone();
+    std::thread::spawn(move || loop {
+        match term::read() {
+            Ok(ev) => {
+                if reader_out.send(Some(ev)).is_err() {
+                    return;
+                }
+            }
+            Err(e) => {
+                info!("Cannot read terminal input: {}", e);
+                return;
+            }
+        }
+    });
+    let renderer = TtyRenderer {
+        out,
+        dim: dim.clone(),
+        pixels: vec![],
+        caps: Capabilities::all(),
+    };
+    let input = TtyInput {
+        dim,
+        input_in,
+        input_out,
+    };
+    Ok((renderer, input))
+}
+
+impl Drop for TtyRenderer {
+    fn drop(&mut self) {
+        let _ = crossterm::execute!(
+            self.out,
+            style::ResetColor,
+            cursor::Show,
+            term::DisableMouseCapture,
+            terminal::LeaveAlternateScreen
+        );
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Rasterize a display list, row by row.
+fn rasterize(width: usize, height: usize, list: &DisplayList) -> Vec<(u8, u8, u8)> {
+    let mut pixels = vec![(0, 0, 0); width * height];
+    for r in list.rects.iter() {
+        let (x0, y0) = (r.x as usize, r.y as usize);
+        let x1 = (x0 + r.width as usize).min(width);
+        let y1 = (y0 + r.height as usize).min(height);
+        if x0 >= x1 {
+            continue;
+        }
+        for y in y0..y1 {
+            for pixel in pixels[y * width + x0..y * width + x1].iter_mut() {
+                *pixel = r.color;
+            }
+        }
+    }
+    pixels
+}
+
+fn rgb((r, g, b): (u8, u8, u8)) -> style::Color {
+    style::Color::Rgb { r, g, b }
+}
+
+impl TtyRenderer {
+    /// Write the last frame (if any) and the overlay, and flush them.
+    fn present(&mut self, overlay: &Overlay) -> IcmtResult<()> {
+        let width = nat_ceil(&self.dim.width) as usize;
+        let height = nat_ceil(&self.dim.height) as usize;
+        if self.pixels.len() == width * height {
+            let mut colors = None;
+            for row in 0..height / 2 {
+                queue!(self.out, cursor::MoveTo(0, row as u16))?;
+                for col in 0..width {
+                    let upper = self.pixels[2 * row * width + col];
+                    let lower = self.pixels[(2 * row + 1) * width + col];
+                    if colors != Some((upper, lower)) {
+                        queue!(
+                            self.out,
+                            style::SetForegroundColor(rgb(upper)),
+                            style::SetBackgroundColor(rgb(lower))
+                        )?;
+                        colors = Some((upper, lower));
+                    }
+                    queue!(self.out, style::Print(UPPER_HALF_BLOCK))?;
+                }
+            }
+        }
+        if let Some((summary, queue_depth)) = &overlay.hud {
+            queue!(
+                self.out,
+                style::SetForegroundColor(style::Color::Green),
+                style::SetBackgroundColor(style::Color::Black)
+            )?;
+            for (row, line) in hud_lines(summary, *queue_depth).iter().enumerate() {
+                queue!(self.out, cursor::MoveTo(0, row as u16), style::Print(line))?;
+            }
+        }
+        let status = match &overlay.status {
+            ConnectionStatus::Connected => String::new(),
+            status => format!("ic-mt: {}", status),
+        };
+        queue!(
+            self.out,
+            style::ResetColor,
+            cursor::MoveTo(0, (height / 2) as u16),
+            terminal::Clear(terminal::ClearType::CurrentLine),
+            style::Print(status)
+        )?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+impl Renderer for TtyRenderer {
+    fn dim(&self) -> graphics::Dim {
+        self.dim.clone()
+    }
+
+    fn render(
+        &mut self,
+        dim: &graphics::Dim,
+        frame: &graphics::Result,
+        overlay: &Overlay,
+    ) -> IcmtResult<()> {
+        let list = DisplayList::compile_for(dim, frame, &self.caps);
+        self.dim = dim.clone();
+        self.pixels = rasterize(
+            nat_ceil(&dim.width) as usize,
+            nat_ceil(&dim.height) as usize,
+            &list,
+        );
+        self.present(overlay)
+    }
+
+    fn redraw(&mut self, overlay: &Overlay) -> IcmtResult<()> {
+        self.present(overlay)
+    }
+
+    fn negotiated(&mut self, caps: &Capabilities) {
+        self.caps = caps.clone();
+    }
+}
+
+impl InputSource for TtyInput {
+    fn next_input(&mut self, timeout: Duration) -> IcmtResult<Option<Input>> {
+        let tty_event = match self.input_in.recv_timeout(timeout) {
+            Ok(Some(tty_event)) => tty_event,
+            Ok(None) | Err(mpsc::RecvTimeoutError::Timeout) => return Ok(None),
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                return Err(IcmtError::Channel("terminal input has stopped".to_string()))
+            }
+        };
+        trace!("terminal event {:?}", tty_event);
+        let event = match tty_event {
+            TtyEvent::Key(KeyEvent {
+                code: KeyCode::F(1),
+                ..
+            }) => return Ok(Some(Input::ToggleHud)),
+            // (as in the window; ctrl+c, in raw mode, is a key like any other.)
+            TtyEvent::Key(KeyEvent {
+                code: KeyCode::Esc, ..
+            }) => event::Event::Quit,
+            TtyEvent::Key(key) => match translate_key(&key) {
+                Some(key) => event::Event::KeyDown(vec![key]),
+                None => return Ok(None),
+            },
+            TtyEvent::Mouse(mouse) => match mouse.kind {
+                MouseEventKind::Down(_) => {
+                    let (x, y) = (mouse.column as u32, 2 * mouse.row as u32);
+                    if x >= nat_ceil(&self.dim.width) || y >= nat_ceil(&self.dim.height) {
+                        return Ok(None);
+                    }
+                    event::Event::MouseDown(graphics::Pos {
+                        x: Nat::from(x),
+                        y: Nat::from(y),
+                    })
+                }
+                _ => return Ok(None),
+            },
+            TtyEvent::Resize(cols, rows) => {
+                self.dim = dim_of_size(cols, rows);
+                event::Event::WindowSize(self.dim.clone())
+            }
+        };
+        Ok(Some(Input::Event(event)))
+    }
+
+    fn waker(&self) -> Waker {
+        let input_out = std::sync::Mutex::new(self.input_out.clone());
+        Arc::new(move || {
+            let _ = input_out.lock().unwrap().send(None);
+        })
+    }
+}
+
+/// The key of a terminal key event, as the canister sees it.
+fn translate_key(key: &KeyEvent) -> Option<event::KeyEventInfo> {
+    let shift = key.modifiers.contains(KeyModifiers::SHIFT);
+    let (key_name, shift) = match key.code {
+        KeyCode::Char(c) => (c.to_string(), shift || c.is_uppercase()),
+        KeyCode::Enter => ("Enter".to_string(), shift),
+        KeyCode::Tab => ("Tab".to_string(), shift),
+        KeyCode::BackTab => ("Tab".to_string(), true),
+        KeyCode::Backspace => ("Backspace".to_string(), shift),
+        KeyCode::Left => ("ArrowLeft".to_string(), shift),
+        KeyCode::Right => ("ArrowRight".to_string(), shift),
+        KeyCode::Up => ("ArrowUp".to_string(), shift),
+        KeyCode::Down => ("ArrowDown".to_string(), shift),
+        code => {
+            info!("Unrecognized key code, ignoring event: {:?}", code);
+            return None;
+        }
+    };
+    Some(event::KeyEventInfo {
+        key: key_name,
+        alt: key.modifiers.contains(KeyModifiers::ALT),
+        ctrl: key.modifiers.contains(KeyModifiers::CONTROL),
+        meta: false, // (terminals do not report it.)
+        shift,
+    })
+}
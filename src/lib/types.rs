@@ -1,22 +1,83 @@
 //! Types of data sent to and from the game service canister.
 
-use crate::cli::ConnectCtx;
+use crate::session::ConnectCtx;
+use crate::error::IcmtResult;
 use chrono::prelude::*;
 use num_traits::cast::ToPrimitive;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 pub type Nat = candid::Nat;
 
-/// temp hack: username and user-chosen color
-pub type UserInfoCli = (String, (Nat, Nat, Nat));
+/// User profile: name, colors and (optional) avatar, for the user's events.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserProfile {
+    pub name: String,
+    /// Foreground (text) color.
+    #[serde(default = "default_fg")]
+    pub fg: (u8, u8, u8),
+    /// Background color.
+    #[serde(default)]
+    pub bg: (u8, u8, u8),
+    #[serde(default)]
+    pub avatar: Option<String>,
+}
+
+fn default_fg() -> (u8, u8, u8) {
+    (255, 255, 255)
+}
+
+impl UserProfile {
+    pub fn new(name: String) -> UserProfile {
+        UserProfile {
+            name,
+            fg: default_fg(),
+            bg: (0, 0, 0),
+            avatar: None,
+        }
+    }
+
+    /// Parse a profile from RON text: either a record, like
+    /// `(name: "Alice", fg: (100, 200, 200))`, or the (older) tuple form
+    /// `("Alice", (100, 200, 200))` of a name and a foreground color.
+    pub fn from_ron(text: &str) -> Result<UserProfile, String> {
+        if let Ok((name, fg)) = ron::de::from_str::<(String, (u8, u8, u8))>(text) {
+            return Ok(UserProfile {
+                fg,
+                ..UserProfile::new(name)
+            });
+        };
+        ron::de::from_str::<UserProfile>(text).map_err(|e| {
+            format!(
+                "{}; expected a user like (name: \"Alice\", fg: (100, 200, 200), bg: (0, 0, 0)) or (\"Alice\", (100, 200, 200))",
+                e
+            )
+        })
+    }
+
+    /// The user info carried by this user's events.
+    pub fn user_info(&self) -> event::UserInfo {
+        let nat3 = |(r, g, b): (u8, u8, u8)| (Nat::from(r), Nat::from(g), Nat::from(b));
+        event::UserInfo {
+            user_name: self.name.clone(),
+            text_color: (nat3(self.fg), nat3(self.bg)),
+            avatar: self.avatar.clone(),
+        }
+    }
+}
 
 /// User kind.
 #[derive(Debug, Clone)]
 pub enum UserKind {
-    Local(UserInfoCli),
+    Local(UserProfile),
     Replay(Vec<event::EventInfo>),
+    /// Watches, without sending any events.
+    Spectator,
 }
 
+/// A natural number, saturated to a `u32`.
 pub fn nat_ceil(n: &Nat) -> u32 {
-    n.0.to_u32().unwrap()
+    n.0.to_u32().unwrap_or(u32::MAX)
 }
 
 pub fn byte_ceil(n: &Nat) -> u8 {
@@ -26,19 +87,19 @@ pub fn byte_ceil(n: &Nat) -> u8 {
     }
 }
 
-/// user name.
-pub fn user_name(ctx: &ConnectCtx) -> Option<String> {
-    match &ctx.cfg.user_kind {
-        UserKind::Local(user_info) => Some(user_info.0.clone()),
-        UserKind::Replay(_) => None,
-    }
-}
-
-/// text color.
-pub fn text_color(ctx: &ConnectCtx) -> Option<(Nat, Nat, Nat)> {
-    match &ctx.cfg.user_kind {
-        UserKind::Local(user_info) => Some(user_info.1.clone()),
-        UserKind::Replay(_) => None,
+/// Form an event from the local user, with a fresh nonce and timestamps.
+pub fn event_info(ctx: &ConnectCtx, event: event::Event) -> event::EventInfo {
+    if let UserKind::Local(ref profile) = ctx.cfg.user_kind {
+        event::EventInfo {
+            user_info: profile.user_info(),
+            nonce: Some(ctx.cfg.nonces.next()),
+            session_id: Some(ctx.cfg.nonces.session_id.clone()),
+            date_time_local: Local::now().to_rfc3339(),
+            date_time_utc: Utc::now().to_rfc3339(),
+            event,
+        }
+    } else {
+        unimplemented!("local events only come from live interaction, the Local user kind.")
     }
 }
 
@@ -46,23 +107,110 @@ pub fn text_color(ctx: &ConnectCtx) -> Option<(Nat, Nat, Nat)> {
 ///
 /// Skip events do nothing but carry meta event info, needed for per-user views.
 pub fn skip_event(ctx: &ConnectCtx) -> event::EventInfo {
-    if let UserKind::Local(_) = ctx.cfg.user_kind {
-        event::EventInfo {
-            user_info: event::UserInfo {
-                user_name: user_name(ctx).unwrap(),
-                text_color: (
-                    text_color(ctx).unwrap(),
-                    (Nat::from(0), Nat::from(0), Nat::from(0)),
-                ),
-            },
-            nonce: None,
-            date_time_local: Local::now().to_rfc3339(),
-            date_time_utc: Utc::now().to_rfc3339(),
-            event: event::Event::Skip,
+    event_info(ctx, event::Event::Skip)
+}
+
+/// Per-session event nonces.
+///
+/// Each session has a random id, and numbers its events from zero;
+/// canisters use the pair to order input and to drop duplicates (e.g.,
+/// after an update call is retried).
+#[derive(Debug, Clone)]
+pub struct Nonces {
+    pub session_id: String,
+    next: Arc<AtomicU64>,
+}
+
+impl Nonces {
+    /// Fresh session, with a random id.
+    pub fn new() -> IcmtResult<Nonces> {
+        use ring::rand::SecureRandom;
+        let mut bytes = [0u8; 8];
+        ring::rand::SystemRandom::new().fill(&mut bytes)?;
+        Ok(Nonces::resume(hex::encode(bytes), 0))
+    }
+
+    /// Continue an existing session, from the given nonce.
+    pub fn resume(session_id: String, next: u64) -> Nonces {
+        Nonces {
+            session_id,
+            next: Arc::new(AtomicU64::new(next)),
+        }
+    }
+
+    /// Next nonce of the session.
+    pub fn next(&self) -> Nat {
+        Nat::from(self.next.fetch_add(1, Ordering::SeqCst))
+    }
+
+    /// Number of nonces issued so far.
+    pub fn count(&self) -> u64 {
+        self.next.load(Ordering::SeqCst)
+    }
+}
+
+/// How replay treats the nonces of captured events.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NonceMode {
+    /// Keep captured nonces and session ids; number any missing ones after them.
+    Preserve,
+    /// Renumber every event, in order, under the replay's own session.
+    Rewrite,
+}
+
+impl std::str::FromStr for NonceMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "preserve" => Ok(NonceMode::Preserve),
+            "rewrite" => Ok(NonceMode::Rewrite),
+            _ => Err(format!(
+                "unknown nonce mode {:?} (expected preserve or rewrite)",
+                s
+            )),
+        }
+    }
+}
+
+/// Assign the nonces of replayed events, consistently with the given mode.
+pub fn replay_nonces(
+    mut events: Vec<event::EventInfo>,
+    mode: &NonceMode,
+    nonces: &Nonces,
+) -> Vec<event::EventInfo> {
+    match mode {
+        NonceMode::Rewrite => {
+            for ev in events.iter_mut() {
+                ev.nonce = Some(nonces.next());
+                ev.session_id = Some(nonces.session_id.clone());
+            }
+        }
+        NonceMode::Preserve => {
+            // Captures made before nonces existed have none; number them
+            // after the largest captured nonce, under the captured session.
+            let session_id = events
+                .iter()
+                .find_map(|ev| ev.session_id.clone())
+                .unwrap_or_else(|| nonces.session_id.clone());
+            let mut next = events
+                .iter()
+                .filter_map(|ev| ev.nonce.clone())
+                .max()
+                .map(|mut n| {
+                    n += 1;
+                    n
+                })
+                .unwrap_or_else(|| Nat::from(0));
+            for ev in events.iter_mut() {
+                if ev.nonce.is_none() {
+                    ev.nonce = Some(next.clone());
+                    ev.session_id = Some(session_id.clone());
+                    next += 1;
+                }
+            }
         }
-    } else {
-        unimplemented!("skip events only come from live interaction, the Local user kind.")
     }
+    events
 }
 
 /// Messages from terminal to service (IC canister).
@@ -72,8 +220,49 @@ pub enum ServiceCall {
     View(graphics::Dim, Vec<event::EventInfo>),
     // Update the state of the remote canister
     Update(Vec<event::EventInfo>, graphics::Request),
-    // To process user request to quit interaction
-    FlushQuit,
+}
+
+impl ServiceCall {
+    /// Candid-encoded argument, after the given extra (leading) arguments.
+    pub fn encode_arg(&self, extra_args: &candid::IDLArgs) -> IcmtResult<Vec<u8>> {
+        let mut builder = candid::ser::IDLBuilder::new();
+        for value in extra_args.args.iter() {
+            builder.value_arg(value)?;
+        }
+        match self {
+            ServiceCall::View(window_dim, evs) => {
+                builder.arg(window_dim)?.arg(evs)?;
+            }
+            ServiceCall::Update(evs, req) => {
+                builder.arg(evs)?.arg(req)?;
+            }
+        };
+        Ok(builder.serialize_to_vec()?)
+    }
+}
+
+/// Connection status, as reported by the view and update tasks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionStatus {
+    // Last service call succeeded
+    Connected,
+    // Service call failed (this many times, so far); retrying after a pause
+    Retrying(usize),
+    // Re-creating the agent, after repeated failures
+    Reconnecting,
+    // Gave up: the task has stopped, with this error
+    Failed(String),
+}
+
+impl std::fmt::Display for ConnectionStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionStatus::Connected => write!(f, "connected"),
+            ConnectionStatus::Retrying(n) => write!(f, "retrying (failed {} times)", n),
+            ConnectionStatus::Reconnecting => write!(f, "reconnecting"),
+            ConnectionStatus::Failed(e) => write!(f, "disconnected: {}", e),
+        }
+    }
 }
 
 /// Message language
@@ -124,6 +313,7 @@ pub mod event {
         pub user_name: String,
         #[serde(rename = "textColor")]
         pub text_color: ((Nat, Nat, Nat), (Nat, Nat, Nat)),
+        pub avatar: Option<String>,
     }
 
     /// Event information (full record).
@@ -132,6 +322,8 @@ pub mod event {
         #[serde(rename = "userInfo")]
         pub user_info: UserInfo,
         pub nonce: Option<Nat>,
+        #[serde(rename = "sessionId")]
+        pub session_id: Option<String>,
         #[serde(rename = "dateTimeUtc")]
         pub date_time_utc: String,
         #[serde(rename = "dateTimeLocal")]
@@ -153,6 +345,23 @@ pub mod event {
         WindowSize(super::graphics::Dim),
         #[serde(rename = "clipBoard")]
         ClipBoard(String),
+        /// Timer tick, with the milliseconds elapsed since the last one.
+        #[serde(rename = "tick")]
+        Tick(Nat),
+    }
+    impl Event {
+        /// The event's kind, as named by the protocol (see `handshake`).
+        pub fn kind(&self) -> &'static str {
+            match self {
+                Event::Skip => "skip",
+                Event::Quit => "quit",
+                Event::KeyDown(_) => "keyDown",
+                Event::MouseDown(_) => "mouseDown",
+                Event::WindowSize(_) => "windowSize",
+                Event::ClipBoard(_) => "clipBoard",
+                Event::Tick(_) => "tick",
+            }
+        }
     }
     /// Keyboard event information.
     #[derive(Clone, Debug, CandidType, Deserialize, Hash, PartialEq, Eq)]
@@ -238,6 +447,15 @@ pub mod graphics {
         #[serde(rename = "node")]
         Node(Box<Node>),
     }
+    impl Elm {
+        /// The element's kind, as named by the protocol (see `handshake`).
+        pub fn kind(&self) -> &'static str {
+            match self {
+                Elm::Rect(_, _) => "rect",
+                Elm::Node(_) => "node",
+            }
+        }
+    }
     /// Elements
     pub type Elms = Vec<Elm>;
     /// Named elements
@@ -251,6 +469,15 @@ pub mod graphics {
         #[serde(rename = "redraw")]
         Redraw(NamedElms),
     }
+    impl Out {
+        /// The output's kind, as named by the protocol (see `handshake`).
+        pub fn kind(&self) -> &'static str {
+            match self {
+                Out::Draw(_) => "draw",
+                Out::Redraw(_) => "redraw",
+            }
+        }
+    }
     /// Result
     #[derive(Clone, Debug, CandidType, Deserialize, Hash, PartialEq, Eq)]
     pub enum Result {
@@ -260,3 +487,94 @@ pub mod graphics {
         Err(Option<String>),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(session_id: Option<&str>, nonce: Option<u64>) -> event::EventInfo {
+        event::EventInfo {
+            user_info: event::UserInfo {
+                user_name: "tester".to_string(),
+                text_color: (
+                    (Nat::from(255), Nat::from(255), Nat::from(255)),
+                    (Nat::from(0), Nat::from(0), Nat::from(0)),
+                ),
+                avatar: None,
+            },
+            nonce: nonce.map(Nat::from),
+            session_id: session_id.map(|s| s.to_string()),
+            date_time_utc: "2021-01-01T00:00:00+00:00".to_string(),
+            date_time_local: "2021-01-01T00:00:00+00:00".to_string(),
+            event: event::Event::Skip,
+        }
+    }
+
+    /// Each event's session id and nonce.
+    fn numbering(events: &[event::EventInfo]) -> Vec<(Option<String>, Option<Nat>)> {
+        events
+            .iter()
+            .map(|ev| (ev.session_id.clone(), ev.nonce.clone()))
+            .collect()
+    }
+
+    fn numbered(session_id: &str, nonces: &[u64]) -> Vec<(Option<String>, Option<Nat>)> {
+        nonces
+            .iter()
+            .map(|n| (Some(session_id.to_string()), Some(Nat::from(*n))))
+            .collect()
+    }
+
+    #[test]
+    fn nonces_count_up() {
+        let nonces = Nonces::resume("abc".to_string(), 5);
+        assert_eq!(nonces.next(), Nat::from(5));
+        assert_eq!(nonces.clone().next(), Nat::from(6));
+        assert_eq!(nonces.count(), 7);
+        assert_ne!(Nonces::new().unwrap().session_id, Nonces::new().unwrap().session_id);
+    }
+
+    #[test]
+    fn numbers_events_without_nonces() {
+        // (captured before nonces existed.)
+        let events = vec![event(None, None), event(None, None), event(None, None)];
+        let nonces = Nonces::resume("replay".to_string(), 0);
+        let events = replay_nonces(events, &NonceMode::Preserve, &nonces);
+        assert_eq!(numbering(&events), numbered("replay", &[0, 1, 2]));
+    }
+
+    #[test]
+    fn preserve_keeps_captured_nonces() {
+        let events = vec![
+            event(Some("captured"), Some(3)),
+            event(None, None),
+            event(Some("captured"), Some(7)),
+            event(None, None),
+        ];
+        let nonces = Nonces::resume("replay".to_string(), 100);
+        let events = replay_nonces(events, &NonceMode::Preserve, &nonces);
+        assert_eq!(numbering(&events), numbered("captured", &[3, 8, 7, 9]));
+        // (The replay's own session is untouched.)
+        assert_eq!(nonces.count(), 100);
+    }
+
+    #[test]
+    fn rewrite_renumbers_under_new_session() {
+        let events = vec![
+            event(Some("captured"), Some(7)),
+            event(None, None),
+            event(Some("captured"), Some(3)),
+        ];
+        let nonces = Nonces::resume("replay".to_string(), 100);
+        let events = replay_nonces(events, &NonceMode::Rewrite, &nonces);
+        assert_eq!(numbering(&events), numbered("replay", &[100, 101, 102]));
+        assert_eq!(nonces.next(), Nat::from(103));
+    }
+
+    #[test]
+    fn nonce_modes() {
+        assert_eq!("preserve".parse::<NonceMode>(), Ok(NonceMode::Preserve));
+        assert_eq!("rewrite".parse::<NonceMode>(), Ok(NonceMode::Rewrite));
+        assert!("keep".parse::<NonceMode>().is_err());
+    }
+}
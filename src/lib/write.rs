@@ -1,9 +1,15 @@
 use candid::Encode;
+use sdl2::render::Canvas;
+use sdl2::surface::Surface;
 use std::io::Write;
 
 use crate::cli::*;
-use crate::error::IcmtResult;
-use crate::types::{event, graphics};
+use crate::display::DisplayList;
+use crate::draw::draw_list;
+use crate::error::{io_err, IcmtResult};
+use crate::frontend::CaptureSink;
+use crate::handshake::Capabilities;
+use crate::types::{event, graphics, nat_ceil};
 use chrono::prelude::*;
 
 pub fn write_gifs(
@@ -24,26 +30,37 @@ pub fn write_gifs(
                 "{}/icmt-{}-{}x{}-events.did",
                 cli.capture_output_path, local_time, window_dim.width, window_dim.height
             );
-            let mut output = File::create(&events_path)?;
+            let mut output = File::create(&events_path).map_err(io_err(&events_path))?;
             let events_bytes = Encode!(&events)?;
             let events_hex = hex::encode(&events_bytes);
-            output.write(&events_hex.as_bytes())?;
+            output
+                .write(&events_hex.as_bytes())
+                .map_err(io_err(&events_path))?;
             println!(
                 "Wrote {} events as {} bytes to\n\t{}",
                 events.len(),
                 events_bytes.len(),
                 events_path
             );
+            if let (Some(first), Some(last)) = (events.first(), events.last()) {
+                if let (Some(session_id), Some(n0), Some(n1)) =
+                    (&last.session_id, &first.nonce, &last.nonce)
+                {
+                    println!("\tSession {}, nonces {} to {}", session_id, n0, n1);
+                }
+            }
         }
         if graphics.len() > 0 {
             let graphics_path = format!(
                 "{}/icmt-{}-{}x{}-graphics.did",
                 cli.capture_output_path, local_time, window_dim.width, window_dim.height
             );
-            let mut output = File::create(&graphics_path)?;
+            let mut output = File::create(&graphics_path).map_err(io_err(&graphics_path))?;
             let graphics_bytes = Encode!(&graphics)?;
             let graphics_hex = hex::encode(&graphics_bytes);
-            output.write(&graphics_hex.as_bytes())?;
+            output
+                .write(&graphics_hex.as_bytes())
+                .map_err(io_err(&graphics_path))?;
             println!(
                 "Wrote {} graphics frames as {} bytes to\n\t{}",
                 graphics.len(),
@@ -56,7 +73,7 @@ pub fn write_gifs(
                 "{}/icmt-{}-{}x{}-video.gif",
                 cli.capture_output_path, local_time, window_dim.width, window_dim.height
             );
-            let mut output = File::create(&video_path)?;
+            let mut output = File::create(&video_path).map_err(io_err(&video_path))?;
             gif.write(&mut output)?;
             println!(
                 "Wrote {} video frames to\n\t{}",
@@ -65,10 +82,90 @@ pub fn write_gifs(
             );
             println!("Removing {} .BMP files...", bmp_paths.len());
             for bmp_file in bmp_paths.iter() {
-                std::fs::remove_file(bmp_file)?;
+                std::fs::remove_file(bmp_file).map_err(io_err(bmp_file))?;
             }
             println!("Done: Removed {} .BMP files.", bmp_paths.len());
         }
     }
     Ok(())
 }
+
+/// Captures a session as files: a GIF video of its frames, its events and
+/// (with `--all-graphics`) the graphics of its update responses.
+pub struct GifCapture {
+    cli: CliOpt,
+    canvas: Canvas<Surface<'static>>,
+    bmp_paths: Vec<String>,
+    caps: Capabilities,
+}
+
+fn surface_canvas(dim: &graphics::Dim) -> IcmtResult<Canvas<Surface<'static>>> {
+    let surface = Surface::new(
+        nat_ceil(&dim.width),
+        nat_ceil(&dim.height),
+        sdl2::pixels::PixelFormatEnum::RGBA8888,
+    )?;
+    Ok(surface.into_canvas()?)
+}
+
+impl GifCapture {
+    pub fn new(cli: &CliOpt, dim: &graphics::Dim) -> IcmtResult<GifCapture> {
+        let capout = std::path::Path::new(&cli.capture_output_path);
+        if !capout.exists() {
+            std::fs::create_dir_all(&cli.capture_output_path)
+                .map_err(io_err(&cli.capture_output_path))?;
+        };
+        Ok(GifCapture {
+            cli: cli.clone(),
+            canvas: surface_canvas(dim)?,
+            bmp_paths: vec![],
+            caps: Capabilities::all(),
+        })
+    }
+}
+
+impl CaptureSink for GifCapture {
+    fn frame(&mut self, dim: &graphics::Dim, frame: &graphics::Result) -> IcmtResult<()> {
+        if self.cli.no_capture {
+            return Ok(());
+        }
+        draw_list(&mut self.canvas, &DisplayList::compile_for(dim, frame, &self.caps))?;
+        self.canvas.present();
+        let path = format!(
+            "{}/screen-{}x{}-{}.bmp",
+            self.cli.capture_output_path,
+            dim.width,
+            dim.height,
+            Local::now().to_rfc3339()
+        );
+        self.canvas
+            .surface()
+            .save_bmp(&path)
+            .map_err(|e| format!("{}: {}", path, e))?;
+        self.bmp_paths.push(path);
+        Ok(())
+    }
+
+    fn resize(&mut self, old_dim: &graphics::Dim, dim: &graphics::Dim) -> IcmtResult<()> {
+        write_gifs(&self.cli, old_dim, vec![], &vec![], &self.bmp_paths)?;
+        self.bmp_paths = vec![];
+        // Re-size canvas by re-creating it.
+        self.canvas = surface_canvas(dim)?;
+        Ok(())
+    }
+
+    fn finish(
+        &mut self,
+        dim: &graphics::Dim,
+        events: &Vec<event::EventInfo>,
+        graphics: &Vec<graphics::Result>,
+    ) -> IcmtResult<()> {
+        write_gifs(&self.cli, dim, events.clone(), graphics, &self.bmp_paths)?;
+        self.bmp_paths = vec![];
+        Ok(())
+    }
+
+    fn negotiated(&mut self, caps: &Capabilities) {
+        self.caps = caps.clone();
+    }
+}
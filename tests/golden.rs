@@ -0,0 +1,267 @@
+//! Golden-image tests for the renderer.
+//!
+//! Each fixture (a graphics result, hand-written below or captured in
+//! `tests/golden/captured`) is drawn offscreen, as a capture draws it, and
+//! compared with its golden image, `tests/golden/<name>.png`.  The canvas
+//! starts magenta, so that an undrawn frame differs from a black one.
+//!
+//! To add captured fixtures, copy the `*-WxH-graphics.did` file of a session
+//! run with `--all-graphics` into `tests/golden/captured`; each of its
+//! results is a fixture.  (`sample-320x240-graphics.did` is a small text
+//! screen, in that format: drawn, redrawn, and an error result.)  To
+//! (re)write the golden images, run
+//! `ICMT_BLESS=1 cargo test --test golden`, and check the new images.
+//! On a mismatch, the image drawn goes to `target/golden/<name>.png`.
+
+use candid::{Decode, Nat};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::surface::Surface;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use icmt::draw::draw_frame;
+use icmt::types::graphics::{self, Elm, Fill, Node, Out, Rect};
+
+struct Fixture {
+    name: String,
+    dim: graphics::Dim,
+    result: graphics::Result,
+}
+
+fn nat(n: u64) -> Nat {
+    Nat::from(n)
+}
+
+fn dim(width: u64, height: u64) -> graphics::Dim {
+    graphics::Dim {
+        width: nat(width),
+        height: nat(height),
+    }
+}
+
+fn color(r: u64, g: u64, b: u64) -> graphics::Color {
+    (nat(r), nat(g), nat(b))
+}
+
+fn rect(x: u64, y: u64, w: u64, h: u64, fill: Fill) -> Elm {
+    Elm::Rect(Rect::new(nat(x), nat(y), nat(w), nat(h)), fill)
+}
+
+fn node(x: u64, y: u64, w: u64, h: u64, fill: Fill, elms: Vec<Elm>) -> Elm {
+    Elm::Node(Box::new(Node {
+        rect: Rect::new(nat(x), nat(y), nat(w), nat(h)),
+        fill,
+        elms,
+    }))
+}
+
+fn draw(elm: Elm) -> graphics::Result {
+    graphics::Result::Ok(Out::Draw(elm))
+}
+
+fn hand_written() -> Vec<Fixture> {
+    let fixture = |name: &str, result| Fixture {
+        name: name.to_string(),
+        dim: dim(64, 48),
+        result,
+    };
+    let red = || color(255, 0, 0);
+    let green = || color(0, 255, 0);
+    let blue = || color(0, 0, 255);
+    let white = || color(255, 255, 255);
+    vec![
+        fixture(
+            "fill-closed",
+            draw(rect(4, 4, 20, 10, Fill::Closed(red()))),
+        ),
+        fixture(
+            "fill-open",
+            // (the border width is not drawn; outlines are one pixel wide.)
+            draw(node(
+                0,
+                0,
+                64,
+                48,
+                Fill::None,
+                vec![
+                    rect(4, 4, 20, 10, Fill::Open(green(), nat(3))),
+                    rect(30, 4, 2, 10, Fill::Open(green(), nat(1))),
+                    rect(36, 4, 1, 1, Fill::Open(green(), nat(1))),
+                ],
+            )),
+        ),
+        fixture(
+            "fill-none",
+            draw(rect(4, 4, 20, 10, Fill::None)),
+        ),
+        fixture(
+            "nested-nodes",
+            draw(node(
+                10,
+                5,
+                40,
+                30,
+                Fill::Closed(blue()),
+                vec![node(
+                    5,
+                    5,
+                    20,
+                    10,
+                    Fill::Open(white(), nat(1)),
+                    vec![rect(2, 2, 4, 4, Fill::Closed(color(255, 255, 0)))],
+                )],
+            )),
+        ),
+        fixture(
+            "clipped",
+            // past the frame's edges, and (saturated) past any edge at all.
+            draw(node(
+                0,
+                0,
+                64,
+                48,
+                Fill::None,
+                vec![
+                    rect(56, 40, 20, 20, Fill::Closed(red())),
+                    rect(60, 2, 10, 6, Fill::Open(green(), nat(1))),
+                    rect(2, 2, u64::MAX, 3, Fill::Closed(blue())),
+                    rect(u64::MAX, 0, 4, 4, Fill::Closed(white())),
+                ],
+            )),
+        ),
+        fixture(
+            "colors-saturated",
+            draw(rect(8, 8, 16, 16, Fill::Closed(color(300, 128, 1 << 40)))),
+        ),
+        fixture(
+            "redraw-screen",
+            graphics::Result::Ok(Out::Redraw(vec![(
+                "screen".to_string(),
+                rect(8, 8, 16, 8, Fill::Closed(green())),
+            )])),
+        ),
+        fixture(
+            "redraw-other-elements",
+            graphics::Result::Ok(Out::Redraw(vec![
+                ("hud".to_string(), rect(0, 0, 64, 48, Fill::Closed(red()))),
+                ("screen".to_string(), rect(8, 8, 16, 8, Fill::Closed(green()))),
+            ])),
+        ),
+        fixture(
+            "redraw-without-screen",
+            graphics::Result::Ok(Out::Redraw(vec![(
+                "hud".to_string(),
+                rect(0, 0, 64, 48, Fill::Closed(red())),
+            )])),
+        ),
+        fixture(
+            "error-result",
+            graphics::Result::Err(Some("fixture".to_string())),
+        ),
+    ]
+}
+
+/// Fixtures from captured graphics files (`*-WxH-graphics.did`, in hex).
+fn captured() -> Vec<Fixture> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/captured");
+    let mut paths: Vec<PathBuf> = match fs::read_dir(&dir) {
+        Ok(entries) => entries.map(|e| e.unwrap().path()).collect(),
+        Err(_) => return vec![],
+    };
+    paths.sort();
+    let mut fixtures = vec![];
+    for path in paths {
+        let stem = path.file_stem().unwrap().to_string_lossy().to_string();
+        let size = match stem.strip_suffix("-graphics") {
+            Some(prefix) => prefix.rsplit('-').next().unwrap().to_string(),
+            None => continue,
+        };
+        let mut wh = size.split('x').map(|n| n.parse::<u64>().unwrap());
+        let dim = dim(wh.next().unwrap(), wh.next().unwrap());
+        let hex = fs::read_to_string(&path).unwrap();
+        let bytes = hex::decode(hex.trim()).unwrap();
+        let results = Decode!(&bytes, Vec<graphics::Result>).unwrap();
+        for (i, result) in results.into_iter().enumerate() {
+            fixtures.push(Fixture {
+                name: format!("captured-{}-{}", stem, i),
+                dim: dim.clone(),
+                result,
+            });
+        }
+    }
+    fixtures
+}
+
+/// Draw a fixture offscreen; its pixels, as RGB rows.
+fn render(fixture: &Fixture) -> (u32, u32, Vec<u8>) {
+    let (width, height) = (
+        icmt::types::nat_ceil(&fixture.dim.width),
+        icmt::types::nat_ceil(&fixture.dim.height),
+    );
+    let surface = Surface::new(width, height, PixelFormatEnum::RGBA8888).unwrap();
+    let mut canvas = surface.into_canvas().unwrap();
+    canvas.set_draw_color(Color::RGB(255, 0, 255));
+    canvas.clear();
+    draw_frame(&mut canvas, &fixture.dim, &fixture.result).unwrap();
+    canvas.present();
+    let pixels = canvas.read_pixels(None, PixelFormatEnum::RGB24).unwrap();
+    (width, height, pixels)
+}
+
+fn read_png(path: &Path) -> (u32, u32, Vec<u8>) {
+    let decoder = png::Decoder::new(File::open(path).unwrap());
+    let (info, mut reader) = decoder.read_info().unwrap();
+    assert!(matches!(info.color_type, png::ColorType::RGB));
+    let mut pixels = vec![0; info.buffer_size()];
+    reader.next_frame(&mut pixels).unwrap();
+    (info.width, info.height, pixels)
+}
+
+fn write_png(path: &Path, (width, height, pixels): &(u32, u32, Vec<u8>)) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path).unwrap()), *width, *height);
+    encoder.set_color(png::ColorType::RGB);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header().unwrap().write_image_data(pixels).unwrap();
+}
+
+#[test]
+fn golden_images() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let bless = std::env::var("ICMT_BLESS").is_ok();
+    let mut failures = vec![];
+    for fixture in hand_written().into_iter().chain(captured()) {
+        let actual = render(&fixture);
+        let golden_path = root.join(format!("tests/golden/{}.png", fixture.name));
+        if bless {
+            write_png(&golden_path, &actual);
+            continue;
+        }
+        if !golden_path.exists() {
+            failures.push(format!("{}: no golden image", fixture.name));
+            continue;
+        }
+        let golden = read_png(&golden_path);
+        if golden != actual {
+            let differing = if (golden.0, golden.1) == (actual.0, actual.1) {
+                let diff = golden.2.chunks(3).zip(actual.2.chunks(3));
+                format!("{} pixels differ", diff.filter(|(g, a)| g != a).count())
+            } else {
+                format!(
+                    "size {}x{}, not {}x{}",
+                    actual.0, actual.1, golden.0, golden.1
+                )
+            };
+            let actual_path = root.join(format!("target/golden/{}.png", fixture.name));
+            write_png(&actual_path, &actual);
+            failures.push(format!(
+                "{}: {} (drawn: {})",
+                fixture.name,
+                differing,
+                actual_path.display()
+            ));
+        }
+    }
+    assert!(failures.is_empty(), "golden images differ:\n{}", failures.join("\n"));
+}
@@ -0,0 +1,180 @@
+//! Offline traces: a session recorded with `--record-trace` replays with
+//! `--offline-trace`, each call matched with its recording by content.
+//!
+//! The "canister" is itself a trace, written below, so no replica is needed.
+//! The replayed session sends the same events under another session, with
+//! other nonces and timestamps, and its trace's update calls are shuffled:
+//! it still gets each update's own response.
+
+use candid::{Encode, IDLArgs, Nat};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use icmt::error::IcmtResult;
+use icmt::frontend::CaptureSink;
+use icmt::session::{Backend, Session};
+use icmt::trace::TraceEntry;
+use icmt::types::graphics::{self, Elm, Fill, Out, Rect};
+use icmt::types::{event, replay_nonces, NonceMode, Nonces, ServiceCall, UserKind};
+
+const EVENTS: usize = 8;
+
+fn nat(n: u64) -> Nat {
+    Nat::from(n)
+}
+
+fn dim() -> graphics::Dim {
+    graphics::Dim {
+        width: nat(500),
+        height: nat(400),
+    }
+}
+
+/// A distinct frame for each update.
+fn frame(i: u64) -> graphics::Result {
+    let rect = Rect::new(nat(i), nat(i), nat(10), nat(10));
+    graphics::Result::Ok(Out::Draw(Elm::Rect(
+        rect,
+        Fill::Closed((nat(i), nat(0), nat(0))),
+    )))
+}
+
+/// Key presses, numbered in the given session, at the given time.
+fn events(session_id: &str, next_nonce: u64, date_time: &str) -> Vec<event::EventInfo> {
+    let evs = (0..EVENTS)
+        .map(|i| event::EventInfo {
+            user_info: event::UserInfo {
+                user_name: "tester".to_string(),
+                text_color: ((nat(255), nat(255), nat(255)), (nat(0), nat(0), nat(0))),
+                avatar: None,
+            },
+            nonce: None,
+            session_id: None,
+            date_time_utc: date_time.to_string(),
+            date_time_local: date_time.to_string(),
+            event: event::Event::KeyDown(vec![event::KeyEventInfo {
+                key: ((b'a' + i as u8) as char).to_string(),
+                alt: false,
+                ctrl: false,
+                meta: false,
+                shift: false,
+            }]),
+        })
+        .collect();
+    let nonces = Nonces::resume(session_id.to_string(), next_nonce);
+    replay_nonces(evs, &NonceMode::Rewrite, &nonces)
+}
+
+fn write_trace(path: &PathBuf, entries: &[TraceEntry]) {
+    let lines: Vec<String> = entries
+        .iter()
+        .map(|e| serde_json::to_string(e).unwrap())
+        .collect();
+    fs::write(path, lines.join("\n") + "\n").unwrap();
+}
+
+fn read_trace(path: &PathBuf) -> Vec<TraceEntry> {
+    fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+/// Keeps the graphics of the update responses.
+struct Graphics(Arc<Mutex<Vec<graphics::Result>>>);
+
+impl CaptureSink for Graphics {
+    fn frame(&mut self, _dim: &graphics::Dim, _frame: &graphics::Result) -> IcmtResult<()> {
+        Ok(())
+    }
+
+    fn resize(&mut self, _old_dim: &graphics::Dim, _dim: &graphics::Dim) -> IcmtResult<()> {
+        Ok(())
+    }
+
+    fn finish(
+        &mut self,
+        _dim: &graphics::Dim,
+        _events: &Vec<event::EventInfo>,
+        graphics: &Vec<graphics::Result>,
+    ) -> IcmtResult<()> {
+        *self.0.lock().unwrap() = graphics.clone();
+        Ok(())
+    }
+}
+
+/// Replay the events against a trace (one event per update), recording
+/// another trace if asked; the graphics of the update responses.
+async fn replay(
+    trace: &PathBuf,
+    record: Option<&PathBuf>,
+    events: Vec<event::EventInfo>,
+) -> Vec<graphics::Result> {
+    let graphics = Arc::new(Mutex::new(vec![]));
+    let mut builder = Session::builder()
+        .backend(Backend::Trace {
+            path: trace.to_str().unwrap().to_string(),
+            canister_id: "rwlgt-iiaaa-aaaaa-aaaaa-cai".to_string(),
+        })
+        .user(UserKind::Replay(events))
+        .frame_size(1)
+        .window_dim(dim())
+        .capture(Box::new(Graphics(graphics.clone())));
+    if let Some(path) = record {
+        builder = builder.record_trace(path.to_str().unwrap());
+    }
+    builder.build().await.unwrap().run().await.unwrap();
+    let graphics = graphics.lock().unwrap().clone();
+    graphics
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn replays_recorded_session() {
+    let dir = std::env::temp_dir().join(format!("icmt-trace-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let canister = dir.join("canister.jsonl");
+    let recorded = dir.join("recorded.jsonl");
+    let shuffled = dir.join("shuffled.jsonl");
+    let _ = fs::remove_file(&recorded);
+
+    // The "canister": a view, and a frame for each update, in order.
+    let no_args = IDLArgs { args: vec![] };
+    let mut entries = vec![TraceEntry {
+        method: "view".to_string(),
+        arg: ServiceCall::View(dim(), vec![])
+            .encode_arg(&no_args)
+            .unwrap(),
+        response: Encode!(&frame(0)).unwrap(),
+        elapsed_ms: 0,
+    }];
+    for i in 0..EVENTS as u64 {
+        entries.push(TraceEntry {
+            method: "update".to_string(),
+            arg: vec![],
+            response: Encode!(&vec![frame(i + 1)]).unwrap(),
+            elapsed_ms: 0,
+        });
+    }
+    write_trace(&canister, &entries);
+    let expected: Vec<_> = (1..=EVENTS as u64).map(frame).collect();
+
+    // Record a session.
+    let first = events("0123456789abcdef", 0, "2021-01-01T00:00:00+00:00");
+    let graphics = replay(&canister, Some(&recorded), first).await;
+    assert_eq!(graphics, expected);
+
+    // Replay it, offline, with the update calls out of order.
+    let (mut updates, others): (Vec<_>, Vec<_>) = read_trace(&recorded)
+        .into_iter()
+        .partition(|e| e.method == "update");
+    assert_eq!(updates.len(), EVENTS);
+    updates.reverse();
+    write_trace(&shuffled, &[others, updates].concat());
+    let second = events("fedcba9876543210", 100, "2021-06-01T12:00:00+00:00");
+    let graphics = replay(&shuffled, None, second).await;
+    assert_eq!(graphics, expected);
+
+    let _ = fs::remove_dir_all(&dir);
+}